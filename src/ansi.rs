@@ -12,14 +12,18 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::convert::TryFrom;
 use std::fmt;
+use std::iter;
 use std::ops::{Index, IndexMut, Mul};
 use std::str::FromStr;
 
+use vte::ParamsIter;
+
 const COUNT: usize = 269;
 
 /// Factor for automatic computation of dim colors used by terminal.
-const DIM_FACTOR: f32 = 0.66;
+pub const DIM_FACTOR: f32 = 0.66;
 
 #[derive(Debug, Eq, PartialEq, Copy, Clone, Default)]
 pub struct Rgb {
@@ -34,9 +38,9 @@ impl Mul<f32> for Rgb {
 
     fn mul(self, rhs: f32) -> Rgb {
         Rgb {
-            r: (f32::from(self.r) * rhs).max(0.0).min(255.0) as u8,
-            g: (f32::from(self.g) * rhs).max(0.0).min(255.0) as u8,
-            b: (f32::from(self.b) * rhs).max(0.0).min(255.0) as u8,
+            r: (f32::from(self.r) * rhs).clamp(0.0, 255.0) as u8,
+            g: (f32::from(self.g) * rhs).clamp(0.0, 255.0) as u8,
+            b: (f32::from(self.b) * rhs).clamp(0.0, 255.0) as u8,
         }
     }
 }
@@ -77,7 +81,7 @@ impl FromStr for Rgb {
 #[derive(Copy, Clone)]
 pub struct List([Rgb; COUNT]);
 
-impl<'a> From<&'a Colors> for List {
+impl From<&Colors> for List {
     fn from(colors: &Colors) -> List {
         // Type inference fails without this annotation.
         let mut list = List([Rgb::default(); COUNT]);
@@ -292,12 +296,6 @@ pub struct IndexedColor {
     pub color: Rgb,
 }
 
-#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
-struct SelectionColors {
-    pub text: Option<Rgb>,
-    pub background: Option<Rgb>,
-}
-
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct PrimaryColors {
     pub background: Rgb,
@@ -483,6 +481,56 @@ pub enum NamedColor {
     DimForeground,
 }
 
+impl NamedColor {
+    pub fn to_bright(self) -> Self {
+        match self {
+            NamedColor::Foreground => NamedColor::BrightForeground,
+            NamedColor::Black => NamedColor::BrightBlack,
+            NamedColor::Red => NamedColor::BrightRed,
+            NamedColor::Green => NamedColor::BrightGreen,
+            NamedColor::Yellow => NamedColor::BrightYellow,
+            NamedColor::Blue => NamedColor::BrightBlue,
+            NamedColor::Magenta => NamedColor::BrightMagenta,
+            NamedColor::Cyan => NamedColor::BrightCyan,
+            NamedColor::White => NamedColor::BrightWhite,
+            NamedColor::DimForeground => NamedColor::Foreground,
+            NamedColor::DimBlack => NamedColor::Black,
+            NamedColor::DimRed => NamedColor::Red,
+            NamedColor::DimGreen => NamedColor::Green,
+            NamedColor::DimYellow => NamedColor::Yellow,
+            NamedColor::DimBlue => NamedColor::Blue,
+            NamedColor::DimMagenta => NamedColor::Magenta,
+            NamedColor::DimCyan => NamedColor::Cyan,
+            NamedColor::DimWhite => NamedColor::White,
+            val => val,
+        }
+    }
+
+    pub fn to_dim(self) -> Self {
+        match self {
+            NamedColor::Black => NamedColor::DimBlack,
+            NamedColor::Red => NamedColor::DimRed,
+            NamedColor::Green => NamedColor::DimGreen,
+            NamedColor::Yellow => NamedColor::DimYellow,
+            NamedColor::Blue => NamedColor::DimBlue,
+            NamedColor::Magenta => NamedColor::DimMagenta,
+            NamedColor::Cyan => NamedColor::DimCyan,
+            NamedColor::White => NamedColor::DimWhite,
+            NamedColor::Foreground => NamedColor::DimForeground,
+            NamedColor::BrightBlack => NamedColor::Black,
+            NamedColor::BrightRed => NamedColor::Red,
+            NamedColor::BrightGreen => NamedColor::Green,
+            NamedColor::BrightYellow => NamedColor::Yellow,
+            NamedColor::BrightBlue => NamedColor::Blue,
+            NamedColor::BrightMagenta => NamedColor::Magenta,
+            NamedColor::BrightCyan => NamedColor::Cyan,
+            NamedColor::BrightWhite => NamedColor::White,
+            NamedColor::BrightForeground => NamedColor::Foreground,
+            val => val,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Color {
     Named(NamedColor),
@@ -498,12 +546,21 @@ pub enum Attr {
     Dim,
     Italic,
     Underline,
+    DoubleUnderline,
+    Undercurl,
+    DottedUnderline,
+    DashedUnderline,
     BlinkSlow,
     BlinkFast,
     Reverse,
     Hidden,
     Strike,
-    CancelBold,
+    Overline,
+    Framed,
+    Encircled,
+    Superscript,
+    Subscript,
+    ProportionalSpacing,
     CancelBoldDim,
     CancelItalic,
     CancelUnderline,
@@ -511,150 +568,540 @@ pub enum Attr {
     CancelReverse,
     CancelHidden,
     CancelStrike,
+    CancelOverline,
+    CancelFramedEncircled,
+    CancelSuperSubscript,
+    CancelProportionalSpacing,
     Foreground(Color),
     Background(Color),
+    /// Underline color; `None` draws underlines in the foreground color.
+    UnderlineColor(Option<Color>),
 }
 
-pub fn attrs_from_sgr_parameters(parameters: &[i64]) -> Vec<Option<Attr>> {
-    let mut i = 0;
-    let mut attrs = Vec::with_capacity(parameters.len());
-    loop {
-        if i >= parameters.len() {
-            break;
-        }
+pub fn attrs_from_sgr_parameters(params: &mut ParamsIter<'_>) -> Vec<Option<Attr>> {
+    let mut attrs = Vec::with_capacity(params.size_hint().0);
 
-        let attr = match parameters[i] {
-            0 => Some(Attr::Reset),
-            1 => Some(Attr::Bold),
-            2 => Some(Attr::Dim),
-            3 => Some(Attr::Italic),
-            4 => Some(Attr::Underline),
-            5 => Some(Attr::BlinkSlow),
-            6 => Some(Attr::BlinkFast),
-            7 => Some(Attr::Reverse),
-            8 => Some(Attr::Hidden),
-            9 => Some(Attr::Strike),
-            21 => Some(Attr::CancelBold),
-            22 => Some(Attr::CancelBoldDim),
-            23 => Some(Attr::CancelItalic),
-            24 => Some(Attr::CancelUnderline),
-            25 => Some(Attr::CancelBlink),
-            27 => Some(Attr::CancelReverse),
-            28 => Some(Attr::CancelHidden),
-            29 => Some(Attr::CancelStrike),
-            30 => Some(Attr::Foreground(Color::Named(NamedColor::Black))),
-            31 => Some(Attr::Foreground(Color::Named(NamedColor::Red))),
-            32 => Some(Attr::Foreground(Color::Named(NamedColor::Green))),
-            33 => Some(Attr::Foreground(Color::Named(NamedColor::Yellow))),
-            34 => Some(Attr::Foreground(Color::Named(NamedColor::Blue))),
-            35 => Some(Attr::Foreground(Color::Named(NamedColor::Magenta))),
-            36 => Some(Attr::Foreground(Color::Named(NamedColor::Cyan))),
-            37 => Some(Attr::Foreground(Color::Named(NamedColor::White))),
-            38 => {
-                let mut start = 0;
-                if let Some(color) = parse_sgr_color(&parameters[i..], &mut start) {
-                    i += start;
-                    Some(Attr::Foreground(color))
-                } else {
-                    None
-                }
+    while let Some(param) = params.next() {
+        let attr = match param {
+            [0] => Some(Attr::Reset),
+            [1] => Some(Attr::Bold),
+            [2] => Some(Attr::Dim),
+            [3] => Some(Attr::Italic),
+            [4, 0] => Some(Attr::CancelUnderline),
+            [4, 2] => Some(Attr::DoubleUnderline),
+            [4, 3] => Some(Attr::Undercurl),
+            [4, 4] => Some(Attr::DottedUnderline),
+            [4, 5] => Some(Attr::DashedUnderline),
+            [4, ..] => Some(Attr::Underline),
+            [5] => Some(Attr::BlinkSlow),
+            [6] => Some(Attr::BlinkFast),
+            [7] => Some(Attr::Reverse),
+            [8] => Some(Attr::Hidden),
+            [9] => Some(Attr::Strike),
+            [21] => Some(Attr::DoubleUnderline),
+            [22] => Some(Attr::CancelBoldDim),
+            [23] => Some(Attr::CancelItalic),
+            [24] => Some(Attr::CancelUnderline),
+            [25] => Some(Attr::CancelBlink),
+            [26] => Some(Attr::ProportionalSpacing),
+            [27] => Some(Attr::CancelReverse),
+            [28] => Some(Attr::CancelHidden),
+            [29] => Some(Attr::CancelStrike),
+            [30] => Some(Attr::Foreground(Color::Named(NamedColor::Black))),
+            [31] => Some(Attr::Foreground(Color::Named(NamedColor::Red))),
+            [32] => Some(Attr::Foreground(Color::Named(NamedColor::Green))),
+            [33] => Some(Attr::Foreground(Color::Named(NamedColor::Yellow))),
+            [34] => Some(Attr::Foreground(Color::Named(NamedColor::Blue))),
+            [35] => Some(Attr::Foreground(Color::Named(NamedColor::Magenta))),
+            [36] => Some(Attr::Foreground(Color::Named(NamedColor::Cyan))),
+            [37] => Some(Attr::Foreground(Color::Named(NamedColor::White))),
+            [38] => {
+                let mut iter = params.map(|param| param[0]);
+                parse_sgr_color(&mut iter).map(Attr::Foreground)
             }
-            39 => Some(Attr::Foreground(Color::Named(NamedColor::Foreground))),
-            40 => Some(Attr::Background(Color::Named(NamedColor::Black))),
-            41 => Some(Attr::Background(Color::Named(NamedColor::Red))),
-            42 => Some(Attr::Background(Color::Named(NamedColor::Green))),
-            43 => Some(Attr::Background(Color::Named(NamedColor::Yellow))),
-            44 => Some(Attr::Background(Color::Named(NamedColor::Blue))),
-            45 => Some(Attr::Background(Color::Named(NamedColor::Magenta))),
-            46 => Some(Attr::Background(Color::Named(NamedColor::Cyan))),
-            47 => Some(Attr::Background(Color::Named(NamedColor::White))),
-            48 => {
-                let mut start = 0;
-                if let Some(color) = parse_sgr_color(&parameters[i..], &mut start) {
-                    i += start;
-                    Some(Attr::Background(color))
-                } else {
-                    None
-                }
+            [38, params @ ..] => handle_colon_rgb(params).map(Attr::Foreground),
+            [39] => Some(Attr::Foreground(Color::Named(NamedColor::Foreground))),
+            [40] => Some(Attr::Background(Color::Named(NamedColor::Black))),
+            [41] => Some(Attr::Background(Color::Named(NamedColor::Red))),
+            [42] => Some(Attr::Background(Color::Named(NamedColor::Green))),
+            [43] => Some(Attr::Background(Color::Named(NamedColor::Yellow))),
+            [44] => Some(Attr::Background(Color::Named(NamedColor::Blue))),
+            [45] => Some(Attr::Background(Color::Named(NamedColor::Magenta))),
+            [46] => Some(Attr::Background(Color::Named(NamedColor::Cyan))),
+            [47] => Some(Attr::Background(Color::Named(NamedColor::White))),
+            [48] => {
+                let mut iter = params.map(|param| param[0]);
+                parse_sgr_color(&mut iter).map(Attr::Background)
+            }
+            [48, params @ ..] => handle_colon_rgb(params).map(Attr::Background),
+            [49] => Some(Attr::Background(Color::Named(NamedColor::Background))),
+            [50] => Some(Attr::CancelProportionalSpacing),
+            [51] => Some(Attr::Framed),
+            [52] => Some(Attr::Encircled),
+            [53] => Some(Attr::Overline),
+            [54] => Some(Attr::CancelFramedEncircled),
+            [55] => Some(Attr::CancelOverline),
+            [58] => {
+                let mut iter = params.map(|param| param[0]);
+                parse_sgr_color(&mut iter).map(|color| Attr::UnderlineColor(Some(color)))
             }
-            49 => Some(Attr::Background(Color::Named(NamedColor::Background))),
-            90 => Some(Attr::Foreground(Color::Named(NamedColor::BrightBlack))),
-            91 => Some(Attr::Foreground(Color::Named(NamedColor::BrightRed))),
-            92 => Some(Attr::Foreground(Color::Named(NamedColor::BrightGreen))),
-            93 => Some(Attr::Foreground(Color::Named(NamedColor::BrightYellow))),
-            94 => Some(Attr::Foreground(Color::Named(NamedColor::BrightBlue))),
-            95 => Some(Attr::Foreground(Color::Named(NamedColor::BrightMagenta))),
-            96 => Some(Attr::Foreground(Color::Named(NamedColor::BrightCyan))),
-            97 => Some(Attr::Foreground(Color::Named(NamedColor::BrightWhite))),
-            100 => Some(Attr::Background(Color::Named(NamedColor::BrightBlack))),
-            101 => Some(Attr::Background(Color::Named(NamedColor::BrightRed))),
-            102 => Some(Attr::Background(Color::Named(NamedColor::BrightGreen))),
-            103 => Some(Attr::Background(Color::Named(NamedColor::BrightYellow))),
-            104 => Some(Attr::Background(Color::Named(NamedColor::BrightBlue))),
-            105 => Some(Attr::Background(Color::Named(NamedColor::BrightMagenta))),
-            106 => Some(Attr::Background(Color::Named(NamedColor::BrightCyan))),
-            107 => Some(Attr::Background(Color::Named(NamedColor::BrightWhite))),
+            [58, params @ ..] => {
+                handle_colon_rgb(params).map(|color| Attr::UnderlineColor(Some(color)))
+            }
+            [59] => Some(Attr::UnderlineColor(None)),
+            [90] => Some(Attr::Foreground(Color::Named(NamedColor::BrightBlack))),
+            [91] => Some(Attr::Foreground(Color::Named(NamedColor::BrightRed))),
+            [92] => Some(Attr::Foreground(Color::Named(NamedColor::BrightGreen))),
+            [93] => Some(Attr::Foreground(Color::Named(NamedColor::BrightYellow))),
+            [94] => Some(Attr::Foreground(Color::Named(NamedColor::BrightBlue))),
+            [95] => Some(Attr::Foreground(Color::Named(NamedColor::BrightMagenta))),
+            [96] => Some(Attr::Foreground(Color::Named(NamedColor::BrightCyan))),
+            [97] => Some(Attr::Foreground(Color::Named(NamedColor::BrightWhite))),
+            // mintty extensions
+            [73] => Some(Attr::Superscript),
+            [74] => Some(Attr::Subscript),
+            [75] => Some(Attr::CancelSuperSubscript),
+            [100] => Some(Attr::Background(Color::Named(NamedColor::BrightBlack))),
+            [101] => Some(Attr::Background(Color::Named(NamedColor::BrightRed))),
+            [102] => Some(Attr::Background(Color::Named(NamedColor::BrightGreen))),
+            [103] => Some(Attr::Background(Color::Named(NamedColor::BrightYellow))),
+            [104] => Some(Attr::Background(Color::Named(NamedColor::BrightBlue))),
+            [105] => Some(Attr::Background(Color::Named(NamedColor::BrightMagenta))),
+            [106] => Some(Attr::Background(Color::Named(NamedColor::BrightCyan))),
+            [107] => Some(Attr::Background(Color::Named(NamedColor::BrightWhite))),
             _ => None,
         };
 
         attrs.push(attr);
-
-        i += 1;
     }
+
     attrs
 }
 
+/// Handle colon separated color sub-parameters, as in `38:2:cs:r:g:b` or `38:5:idx`.
+///
+/// The color space id of the RGB form is optional (and ignored) so both `38:2:r:g:b` and
+/// `38:2::r:g:b` are accepted.
+fn handle_colon_rgb(params: &[u16]) -> Option<Color> {
+    let rgb_start = if params.len() > 4 { 2 } else { 1 };
+    let rgb_iter = params[rgb_start..].iter().copied();
+    let mut iter = iter::once(params[0]).chain(rgb_iter);
+
+    parse_sgr_color(&mut iter)
+}
+
 /// Parse a color specifier from list of attributes.
-fn parse_sgr_color(attrs: &[i64], i: &mut usize) -> Option<Color> {
-    if attrs.len() < 2 {
+fn parse_sgr_color(params: &mut dyn Iterator<Item = u16>) -> Option<Color> {
+    match params.next() {
+        Some(2) => {
+            // RGB color spec.
+            let mut component = || {
+                let value = params.next()?;
+                u8::try_from(value)
+                    .map_err(|_| eprintln!("Invalid RGB color component: {}", value))
+                    .ok()
+            };
+
+            let r = component()?;
+            let g = component()?;
+            let b = component()?;
+
+            Some(Color::Spec(Rgb { r, g, b }))
+        }
+        Some(5) => {
+            let idx = params.next()?;
+            match u8::try_from(idx) {
+                Ok(idx) => Some(Color::Indexed(idx)),
+                Err(_) => {
+                    eprintln!("Invalid color index: {}", idx);
+                    None
+                }
+            }
+        }
+        Some(attr) => {
+            eprintln!("Unexpected color attr: {}", attr);
+            None
+        }
+        None => None,
+    }
+}
+
+/// Parse a color specification as accepted by `XParseColor`: `rgb:r/g/b` with one to four hex
+/// digits per component, the legacy `#rgb` forms, or an X11 color name.
+pub fn xparse_color(color: &[u8]) -> Option<Rgb> {
+    if let Some(color) = color.strip_prefix(b"#") {
+        parse_legacy_color(color)
+    } else if let Some(color) = color.strip_prefix(b"rgb:") {
+        parse_rgb_color(color)
+    } else {
+        std::str::from_utf8(color).ok().and_then(parse_color_name)
+    }
+}
+
+/// Parse colors in `rgb:r/g/b` format, scaling each component to 8 bits.
+fn parse_rgb_color(color: &[u8]) -> Option<Rgb> {
+    let mut components = color.split(|&b| b == b'/').map(|component| {
+        if component.is_empty() || component.len() > 4 {
+            return None;
+        }
+
+        let value = u32::from_str_radix(std::str::from_utf8(component).ok()?, 16).ok()?;
+        let max = (1 << (4 * component.len())) - 1;
+        Some((value * 0xff / max) as u8)
+    });
+
+    let r = components.next()??;
+    let g = components.next()??;
+    let b = components.next()??;
+    if components.next().is_some() {
         return None;
     }
 
-    match attrs[*i + 1] {
-        2 => {
-            // RGB color spec.
-            if attrs.len() < 5 {
-                eprintln!("Expected RGB color spec; got {:?}", attrs);
-                return None;
-            }
+    Some(Rgb { r, g, b })
+}
 
-            let r = attrs[*i + 2];
-            let g = attrs[*i + 3];
-            let b = attrs[*i + 4];
+/// Parse colors in `#rgb`, `#rrggbb`, `#rrrgggbbb` or `#rrrrggggbbbb` format. Unlike `rgb:` the
+/// digits are the most significant bits, so `#f00` is `#f00000`.
+fn parse_legacy_color(color: &[u8]) -> Option<Rgb> {
+    let digits = color.len() / 3;
+    if !color.len().is_multiple_of(3) || digits == 0 || digits > 4 {
+        return None;
+    }
 
-            *i += 4;
+    let mut components = color.chunks(digits).map(|component| {
+        let value = u32::from_str_radix(std::str::from_utf8(component).ok()?, 16).ok()?;
+        let value = if digits == 1 {
+            value << 4
+        } else {
+            value >> (4 * (digits - 2))
+        };
+        Some(value as u8)
+    });
+
+    Some(Rgb {
+        r: components.next()??,
+        g: components.next()??,
+        b: components.next()??,
+    })
+}
+
+/// Look up a color by its X11 name. Names are case insensitive and may contain spaces, and the
+/// numbered `gray0` to `gray100` are supported; only the common names are known.
+fn parse_color_name(name: &str) -> Option<Rgb> {
+    let name: String = name
+        .chars()
+        .filter(|c| *c != ' ')
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+
+    let level = name
+        .strip_prefix("gray")
+        .or_else(|| name.strip_prefix("grey"));
+    if let Some(level) = level.filter(|level| !level.is_empty()) {
+        let level: u32 = level.parse().ok().filter(|level| *level <= 100)?;
+        let v = ((level * 255 + 50) / 100) as u8;
+        return Some(Rgb { r: v, g: v, b: v });
+    }
 
-            let range = 0..256;
-            if !range.contains(&r) || !range.contains(&g) || !range.contains(&b) {
-                eprintln!("Invalid RGB color spec: ({}, {}, {})", r, g, b);
-                return None;
+    let rgb = match name.as_str() {
+        "black" => 0x000000,
+        "white" => 0xffffff,
+        "red" => 0xff0000,
+        "green" => 0x00ff00,
+        "blue" => 0x0000ff,
+        "yellow" => 0xffff00,
+        "cyan" => 0x00ffff,
+        "magenta" => 0xff00ff,
+        "gray" | "grey" => 0xbebebe,
+        "darkgray" | "darkgrey" => 0xa9a9a9,
+        "lightgray" | "lightgrey" => 0xd3d3d3,
+        "darkred" => 0x8b0000,
+        "darkgreen" => 0x006400,
+        "darkblue" => 0x00008b,
+        "darkcyan" => 0x008b8b,
+        "darkmagenta" => 0x8b008b,
+        "lightblue" => 0xadd8e6,
+        "lightgreen" => 0x90ee90,
+        "lightcyan" => 0xe0ffff,
+        "lightyellow" => 0xffffe0,
+        "orange" => 0xffa500,
+        "darkorange" => 0xff8c00,
+        "purple" => 0xa020f0,
+        "pink" => 0xffc0cb,
+        "brown" => 0xa52a2a,
+        "navy" | "navyblue" => 0x000080,
+        "maroon" => 0xb03060,
+        "gold" => 0xffd700,
+        "violet" => 0xee82ee,
+        "turquoise" => 0x40e0d0,
+        "skyblue" => 0x87ceeb,
+        "steelblue" => 0x4682b4,
+        "royalblue" => 0x4169e1,
+        "forestgreen" => 0x228b22,
+        "seagreen" => 0x2e8b57,
+        "olivedrab" => 0x6b8e23,
+        "salmon" => 0xfa8072,
+        "coral" => 0xff7f50,
+        "tomato" => 0xff6347,
+        "orchid" => 0xda70d6,
+        "khaki" => 0xf0e68c,
+        "beige" => 0xf5f5dc,
+        "ivory" => 0xfffff0,
+        "slategray" | "slategrey" => 0x708090,
+        _ => return None,
+    };
+
+    Some(Rgb {
+        r: (rgb >> 16) as u8,
+        g: (rgb >> 8) as u8,
+        b: rgb as u8,
+    })
+}
+
+/// Parse a decimal number from the bytes of an OSC parameter.
+pub fn parse_number(input: &[u8]) -> Option<u8> {
+    std::str::from_utf8(input).ok()?.parse().ok()
+}
+
+/// Identifiers which can be assigned to a graphic character set.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum CharsetIndex {
+    /// Default set, is designated as ASCII at startup.
+    #[default]
+    G0,
+    G1,
+    G2,
+    G3,
+}
+
+impl CharsetIndex {
+    /// The set designated by `ESC ( F`, `ESC ) F`, `ESC * F` or `ESC + F`.
+    pub fn from_intermediate(intermediate: u8) -> Option<Self> {
+        match intermediate {
+            b'(' => Some(CharsetIndex::G0),
+            b')' => Some(CharsetIndex::G1),
+            b'*' => Some(CharsetIndex::G2),
+            b'+' => Some(CharsetIndex::G3),
+            _ => None,
+        }
+    }
+}
+
+/// Standard or common character sets which can be designated as G0-G3.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum StandardCharset {
+    #[default]
+    Ascii,
+    SpecialCharacterAndLineDrawing,
+    Uk,
+    Dutch,
+    Finnish,
+    French,
+    FrenchCanadian,
+    German,
+    Italian,
+    NorwegianDanish,
+    Spanish,
+    Swedish,
+    Swiss,
+}
+
+impl StandardCharset {
+    /// The set named by the final byte of a designation, including the alternative names of the
+    /// national replacement character sets.
+    pub fn from_final(byte: u8) -> Option<Self> {
+        match byte {
+            b'B' => Some(StandardCharset::Ascii),
+            b'0' => Some(StandardCharset::SpecialCharacterAndLineDrawing),
+            b'A' => Some(StandardCharset::Uk),
+            b'4' => Some(StandardCharset::Dutch),
+            b'C' | b'5' => Some(StandardCharset::Finnish),
+            b'R' | b'f' => Some(StandardCharset::French),
+            b'Q' | b'9' => Some(StandardCharset::FrenchCanadian),
+            b'K' => Some(StandardCharset::German),
+            b'Y' => Some(StandardCharset::Italian),
+            b'E' | b'6' | b'`' => Some(StandardCharset::NorwegianDanish),
+            b'Z' => Some(StandardCharset::Spanish),
+            b'H' | b'7' => Some(StandardCharset::Swedish),
+            b'=' => Some(StandardCharset::Swiss),
+            _ => None,
+        }
+    }
+
+    /// Switch/Map character to the active charset. Ascii is the common case and
+    /// for that we want to do as little as possible.
+    #[inline]
+    pub fn map(self, c: char) -> char {
+        // National sets only replace these twelve characters
+        const NATIONAL: &str = "#@[\\]^_`{|}~";
+
+        let replacements = match self {
+            StandardCharset::Ascii => return c,
+            StandardCharset::SpecialCharacterAndLineDrawing => {
+                return match c {
+                    '_' => '\u{a0}',
+                    '`' => '◆',
+                    'a' => '▒',
+                    'b' => '␉',
+                    'c' => '␌',
+                    'd' => '␍',
+                    'e' => '␊',
+                    'f' => '°',
+                    'g' => '±',
+                    'h' => '␤',
+                    'i' => '␋',
+                    'j' => '┘',
+                    'k' => '┐',
+                    'l' => '┌',
+                    'm' => '└',
+                    'n' => '┼',
+                    'o' => '⎺',
+                    'p' => '⎻',
+                    'q' => '─',
+                    'r' => '⎼',
+                    's' => '⎽',
+                    't' => '├',
+                    'u' => '┤',
+                    'v' => '┴',
+                    'w' => '┬',
+                    'x' => '│',
+                    'y' => '≤',
+                    'z' => '≥',
+                    '{' => 'π',
+                    '|' => '≠',
+                    '}' => '£',
+                    '~' => '·',
+                    _ => c,
+                };
             }
+            // Replacements for each character of `NATIONAL`, with the ones a set leaves alone
+            // repeated as is
+            StandardCharset::Uk => "£@[\\]^_`{|}~",
+            StandardCharset::Dutch => "£¾ĳ½|^_`¨ƒ¼´",
+            StandardCharset::Finnish => "#@ÄÖÅÜ_éäöåü",
+            StandardCharset::French => "£à°ç§^_`éùè¨",
+            StandardCharset::FrenchCanadian => "#àâçêî_ôéùèû",
+            StandardCharset::German => "#§ÄÖÜ^_`äöüß",
+            StandardCharset::Italian => "£§°çé^_ùàòèì",
+            StandardCharset::NorwegianDanish => "#ÄÆØÅÜ_äæøåü",
+            StandardCharset::Spanish => "£§¡Ñ¿^_`°ñç~",
+            StandardCharset::Swedish => "#ÉÄÖÅÜ_éäöåü",
+            StandardCharset::Swiss => "ùàéçêîèôäöüû",
+        };
 
-            Some(Color::Spec(Rgb {
-                r: r as u8,
-                g: g as u8,
-                b: b as u8,
-            }))
+        match NATIONAL.chars().position(|national| national == c) {
+            Some(index) => replacements.chars().nth(index).unwrap_or(c),
+            None => c,
         }
-        5 => {
-            if attrs.len() < 3 {
-                eprintln!("Expected color index; got {:?}", attrs);
-                None
-            } else {
-                *i += 2;
-                let idx = attrs[*i];
-                match idx {
-                    0..=255 => Some(Color::Indexed(idx as u8)),
-                    _ => {
-                        eprintln!("Invalid color index: {}", idx);
-                        None
-                    }
-                }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Run `CSI <params> m` through the parser and collect the attributes.
+    fn sgr(params: &str) -> Vec<Option<Attr>> {
+        struct Sgr(Vec<Option<Attr>>);
+
+        impl vte::Perform for Sgr {
+            fn csi_dispatch(&mut self, params: &vte::Params, _: &[u8], _: bool, _: char) {
+                self.0 = attrs_from_sgr_parameters(&mut params.iter());
             }
         }
-        _ => {
-            eprintln!("Unexpected color attr: {}", attrs[*i + 1]);
-            None
+
+        let mut parser = vte::Parser::new();
+        let mut sgr = Sgr(Vec::new());
+        for byte in format!("\x1b[{}m", params).bytes() {
+            parser.advance(&mut sgr, byte);
         }
+        sgr.0
+    }
+
+    fn spec(r: u8, g: u8, b: u8) -> Color {
+        Color::Spec(Rgb { r, g, b })
+    }
+
+    #[test]
+    fn parse_truecolor_foreground() {
+        assert_eq!(
+            sgr("38;2;1;2;3"),
+            vec![Some(Attr::Foreground(spec(1, 2, 3)))]
+        );
+    }
+
+    #[test]
+    fn parse_truecolor_background() {
+        assert_eq!(
+            sgr("48;2;255;128;0"),
+            vec![Some(Attr::Background(spec(255, 128, 0)))]
+        );
+    }
+
+    #[test]
+    fn parse_truecolor_out_of_range_component() {
+        assert_eq!(sgr("38;2;300;0;0")[0], None);
+    }
+
+    #[test]
+    fn parse_truecolor_truncated() {
+        assert_eq!(sgr("38;2;1;2"), vec![None]);
+    }
+
+    #[test]
+    fn parse_indexed_foreground() {
+        assert_eq!(
+            sgr("38;5;196"),
+            vec![Some(Attr::Foreground(Color::Indexed(196)))]
+        );
+    }
+
+    #[test]
+    fn parse_colon_truecolor_with_color_space() {
+        assert_eq!(
+            sgr("38:2::1:2:3"),
+            vec![Some(Attr::Foreground(spec(1, 2, 3)))]
+        );
+    }
+
+    #[test]
+    fn parse_colon_truecolor_without_color_space() {
+        assert_eq!(
+            sgr("38:2:1:2:3"),
+            vec![Some(Attr::Foreground(spec(1, 2, 3)))]
+        );
+    }
+
+    #[test]
+    fn parse_colon_indexed_foreground() {
+        assert_eq!(
+            sgr("38:5:196"),
+            vec![Some(Attr::Foreground(Color::Indexed(196)))]
+        );
+    }
+
+    #[test]
+    fn parse_colon_truecolor_background() {
+        assert_eq!(
+            sgr("48:2:0:1:2:3"),
+            vec![Some(Attr::Background(spec(1, 2, 3)))]
+        );
+    }
+
+    #[test]
+    fn parse_colon_indexed_without_index() {
+        assert_eq!(sgr("38:5"), vec![None]);
+    }
+
+    #[test]
+    fn parse_truecolor_followed_by_attributes() {
+        assert_eq!(
+            sgr("1;38;2;1;2;3;4"),
+            vec![
+                Some(Attr::Bold),
+                Some(Attr::Foreground(spec(1, 2, 3))),
+                Some(Attr::Underline)
+            ]
+        );
     }
 }
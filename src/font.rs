@@ -0,0 +1,124 @@
+use rusttype::{Font, Scale};
+
+use std::fs;
+
+/// The faces used for each combination of bold and italic. Only the regular face is required;
+/// missing faces are synthesised from the closest one available.
+pub struct FontFamily {
+    regular: Font<'static>,
+    /// Underline and strikeout metrics of the regular face, in font units.
+    underline: Option<ttf_parser::LineMetrics>,
+    strikeout: Option<ttf_parser::LineMetrics>,
+    bold: Option<Font<'static>>,
+    italic: Option<Font<'static>>,
+    bold_italic: Option<Font<'static>>,
+}
+
+/// Position and thickness of a line drawn through or under text, in pixels.
+#[derive(Debug, Clone, Copy)]
+pub struct LineMetrics {
+    /// Distance of the top of the line above the baseline; negative is below it.
+    pub position: f32,
+    pub thickness: f32,
+}
+
+/// A font to draw a cell with, and the styles that have to be synthesised on top of it.
+pub struct Face<'a> {
+    pub font: &'a Font<'static>,
+    pub embolden: bool,
+    pub oblique: bool,
+}
+
+impl FontFamily {
+    pub fn load(
+        regular: &str,
+        bold: Option<&str>,
+        italic: Option<&str>,
+        bold_italic: Option<&str>,
+    ) -> Self {
+        let regular_data = fs::read(regular).unwrap();
+        let (underline, strikeout) = match ttf_parser::Font::from_data(&regular_data, 0) {
+            Some(font) => (font.underline_metrics(), font.strikeout_metrics()),
+            None => (None, None),
+        };
+
+        FontFamily {
+            // This only succeeds if collection consists of one font
+            regular: Font::try_from_vec(regular_data).unwrap(),
+            underline,
+            strikeout,
+            bold: bold.map(load_font),
+            italic: italic.map(load_font),
+            bold_italic: bold_italic.map(load_font),
+        }
+    }
+
+    pub fn regular(&self) -> &Font<'static> {
+        &self.regular
+    }
+
+    pub fn underline_metrics(&self, scale: Scale) -> LineMetrics {
+        match self.underline {
+            Some(metrics) => self.scale_metrics(metrics, scale),
+            // Without a `post` table go halfway into the descent
+            None => LineMetrics {
+                position: self.regular.v_metrics(scale).descent / 2.0,
+                thickness: (scale.y / 16.0).max(1.0),
+            },
+        }
+    }
+
+    pub fn strikeout_metrics(&self, scale: Scale) -> LineMetrics {
+        match self.strikeout {
+            Some(metrics) => self.scale_metrics(metrics, scale),
+            // Without an `OS/2` table go roughly through the middle of lowercase letters
+            None => LineMetrics {
+                position: self.regular.v_metrics(scale).ascent / 3.0,
+                thickness: self.underline_metrics(scale).thickness,
+            },
+        }
+    }
+
+    fn scale_metrics(&self, metrics: ttf_parser::LineMetrics, scale: Scale) -> LineMetrics {
+        let units = self.regular.scale_for_pixel_height(scale.y);
+
+        LineMetrics {
+            position: f32::from(metrics.position) * units,
+            thickness: (f32::from(metrics.thickness) * units).max(1.0),
+        }
+    }
+
+    pub fn face(&self, bold: bool, italic: bool) -> Face<'_> {
+        let face = |font, embolden, oblique| Face {
+            font,
+            embolden,
+            oblique,
+        };
+
+        match (bold, italic) {
+            (true, true) => match (&self.bold_italic, &self.italic, &self.bold) {
+                (Some(font), _, _) => face(font, false, false),
+                // A slanted face is harder to fake convincingly than a heavier one
+                (None, Some(font), _) => face(font, true, false),
+                (None, None, Some(font)) => face(font, false, true),
+                (None, None, None) => face(&self.regular, true, true),
+            },
+            (true, false) => match &self.bold {
+                Some(font) => face(font, false, false),
+                None => face(&self.regular, true, false),
+            },
+            (false, true) => match &self.italic {
+                Some(font) => face(font, false, false),
+                None => face(&self.regular, false, true),
+            },
+            (false, false) => face(&self.regular, false, false),
+        }
+    }
+}
+
+fn load_font(path: &str) -> Font<'static> {
+    let font_data = fs::read(path).unwrap();
+
+    // This only succeeds if collection consists of one font
+    Font::try_from_vec(font_data).unwrap()
+}
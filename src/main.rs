@@ -1,26 +1,91 @@
 mod ansi;
+mod font;
 mod parser;
 mod renderer;
+mod screen;
 
 use clap::Clap;
 use std::io;
 
 #[derive(Clap)]
 struct Opts {
-    /// Filename to save PNG
+    /// Filename to save the image to; a `.gif` is animated to show blinking text and an `.html`
+    /// page keeps text selectable and links clickable
     #[clap(short, long, default_value = "out.png")]
     out: String,
     /// Font to use (should be a monospaced font)
     #[clap(short, long)]
     font: String,
+    /// Font for bold text; without one bold is synthesised from the regular font
+    #[clap(long)]
+    font_bold: Option<String>,
+    /// Font for italic text; without one italics are slanted from the regular font
+    #[clap(long)]
+    font_italic: Option<String>,
+    /// Font for bold italic text; without one it's synthesised from the italic or bold font
+    #[clap(long)]
+    font_bold_italic: Option<String>,
+    /// Terminal width in columns; longer lines wrap
+    #[clap(short, long, default_value = "80")]
+    columns: usize,
+    /// Terminal height in lines; output scrolling off the top is still rendered
+    #[clap(short, long, default_value = "24")]
+    lines: usize,
+    /// Screen buffer to render: primary, alternate or whichever was active at the end of input
+    #[clap(long, default_value = "active", possible_values = &["primary", "alternate", "active"])]
+    screen: screen::Buffer,
+    /// Draw bold text in the first eight colors with their bright versions: off, bright-only or
+    /// bright-and-bold
+    #[clap(
+        long,
+        default_value = "off",
+        possible_values = &["off", "bright-only", "bright-and-bold"]
+    )]
+    bold_bright: renderer::BoldBright,
+    /// How blinking text is drawn in static images: show, dim or hide
+    #[clap(long, default_value = "show", possible_values = &["show", "dim", "hide"])]
+    blink: renderer::BlinkStyle,
+    /// Interpret backspace overstrikes (`X\bX` and `_\bX`) as bold and underline, like man pages
+    #[clap(long)]
+    overstrike: bool,
+    /// Underline text linked with OSC 8 that isn't already underlined
+    #[clap(long)]
+    link_underline: bool,
+    /// Draw a title bar with the window title set by the input
+    #[clap(long)]
+    title_bar: bool,
+    /// Only move down on a line feed instead of also returning to the first column, for pty
+    /// captures of programs that turn off `onlcr`
+    #[clap(long)]
+    no_onlcr: bool,
 }
 
 fn main() {
     let opts: Opts = Opts::parse();
 
-    let tokens = parser::parse(io::stdin());
+    let parser_options = parser::Options {
+        overstrike: opts.overstrike,
+        onlcr: !opts.no_onlcr,
+    };
 
-    renderer::render(&tokens, &opts.font, &opts.out);
+    let screen = parser::parse(io::stdin(), opts.columns, opts.lines, &parser_options);
+
+    let fonts = font::FontFamily::load(
+        &opts.font,
+        opts.font_bold.as_deref(),
+        opts.font_italic.as_deref(),
+        opts.font_bold_italic.as_deref(),
+    );
+
+    let options = renderer::Options {
+        buffer: opts.screen,
+        bold_bright: opts.bold_bright,
+        blink: opts.blink,
+        link_underline: opts.link_underline,
+        title_bar: opts.title_bar,
+    };
+
+    renderer::render(&screen, &fonts, &options, &opts.out);
 
     println!("Generated: {}", opts.out);
 }
@@ -1,14 +1,27 @@
-use crate::ansi::{attrs_from_sgr_parameters, Attr, Color, Colors, List, PrimaryColors, Rgb};
+use crate::ansi::{
+    attrs_from_sgr_parameters, parse_number, xparse_color, CharsetIndex, NamedColor,
+    StandardCharset,
+};
+use crate::screen::{Hyperlink, Screen};
 use std::io::Read;
+use vte::Params;
 
-pub fn parse(input: std::io::Stdin) -> Vec<Token> {
-    let mut handle = input.lock();
+pub struct Options {
+    /// Interpret characters printed over a backspace as bold or underline.
+    pub overstrike: bool,
+    /// Return to the first column on every line feed, like a tty with `onlcr` set.
+    pub onlcr: bool,
+}
 
-    let foreground = PrimaryColors::default().foreground;
+pub fn parse(input: std::io::Stdin, columns: usize, lines: usize, options: &Options) -> Screen {
+    let mut handle = input.lock();
 
     let mut statemachine = vte::Parser::new();
     let mut parser = Parser {
-        output: vec![Token::Color(foreground)],
+        screen: Screen::new(columns, lines),
+        overstrike: options.overstrike,
+        onlcr: options.onlcr,
+        backspaced: false,
     };
 
     let mut buf = [0; 2048];
@@ -28,74 +41,300 @@ pub fn parse(input: std::io::Stdin) -> Vec<Token> {
         }
     }
 
-    parser.output
+    parser.screen
 }
 
 #[derive(Debug)]
-pub enum Token {
-    Color(Rgb),
-    Char(char),
+pub struct Parser {
+    pub screen: Screen,
+    /// Interpret characters printed over a backspace as bold or underline.
+    overstrike: bool,
+    /// Treat LF, VT and FF as a new line rather than just moving down.
+    onlcr: bool,
+    /// Whether the last thing received was a backspace.
+    backspaced: bool,
 }
 
-#[derive(Debug)]
-pub struct Parser {
-    pub output: Vec<Token>,
+impl Parser {
+    /// Handle DECSET (`CSI ? Pm h`) and DECRST (`CSI ? Pm l`).
+    fn set_private_mode(&mut self, mode: u16, enable: bool) {
+        let screen = &mut self.screen;
+
+        match (mode, enable) {
+            (5, _) => screen.set_reverse_video(enable),
+            (47, true) | (1047, true) => screen.enter_alt_screen(false),
+            (47, false) => screen.leave_alt_screen(false),
+            (1047, false) => screen.leave_alt_screen(true),
+            (1048, true) => screen.save_cursor(),
+            (1048, false) => screen.restore_cursor(),
+            (1049, true) => {
+                screen.save_cursor();
+                screen.enter_alt_screen(true);
+            }
+            (1049, false) => {
+                screen.leave_alt_screen(false);
+                screen.restore_cursor();
+            }
+            _ => {}
+        }
+    }
 }
 
 impl vte::Perform for Parser {
     fn print(&mut self, c: char) {
-        self.output.push(Token::Char(c));
+        let c = self.screen.map_char(c);
+
+        if self.overstrike && self.backspaced {
+            self.screen.overstrike(c);
+        } else {
+            self.screen.input(c);
+        }
+        self.backspaced = false;
     }
 
-    fn execute(&mut self, _byte: u8) {}
-    fn hook(&mut self, _params: &[i64], _intermediates: &[u8], _ignore: bool, _c: char) {}
+    fn execute(&mut self, byte: u8) {
+        self.backspaced = byte == 0x08;
+
+        match byte {
+            // LF, VT and FF all move down a line. With `onlcr` set (the default for a tty) the
+            // column is reset too, so plain `\n` output renders as expected; programs that turn
+            // it off send their own CR.
+            b'\n' | 0x0b | 0x0c => {
+                if self.onlcr {
+                    self.screen.carriage_return();
+                }
+                self.screen.linefeed();
+            }
+            b'\r' => self.screen.carriage_return(),
+            b'\t' => self.screen.tab_forward(1),
+            0x08 => self.screen.backspace(),
+            // SI and SO
+            0x0f => self.screen.set_active_charset(CharsetIndex::G0),
+            0x0e => self.screen.set_active_charset(CharsetIndex::G1),
+            _ => {}
+        }
+    }
+
+    fn hook(&mut self, _params: &Params, _intermediates: &[u8], _ignore: bool, _c: char) {}
     fn put(&mut self, _byte: u8) {}
     fn unhook(&mut self) {}
-    fn osc_dispatch(&mut self, _params: &[&[u8]], _bell_terminated: bool) {}
+    fn osc_dispatch(&mut self, params: &[&[u8]], _bell_terminated: bool) {
+        self.backspaced = false;
+
+        let screen = &mut self.screen;
+
+        match params {
+            // Set the icon name and window title, or just the title. The title may contain
+            // semicolons.
+            [b"0", title @ ..] | [b"2", title @ ..] if !title.is_empty() => {
+                let title = title.join(&b';');
+                screen.set_title(String::from_utf8_lossy(&title).into_owned());
+            }
+            // Set indexed colors, as any number of `index;spec` pairs
+            [b"4", pairs @ ..] => {
+                for pair in pairs.chunks_exact(2) {
+                    if let (Some(index), Some(color)) =
+                        (parse_number(pair[0]), xparse_color(pair[1]))
+                    {
+                        screen.set_color(usize::from(index), color);
+                    }
+                }
+            }
+            // Set the default foreground, and the background if a second color follows
+            [b"10", specs @ ..] | [b"11", specs @ ..] => {
+                let dynamic = [NamedColor::Foreground, NamedColor::Background];
+                let first = if params[0] == b"10" { 0 } else { 1 };
+
+                for (&color, spec) in dynamic[first..].iter().zip(specs) {
+                    if let Some(rgb) = xparse_color(spec) {
+                        screen.set_color(color as usize, rgb);
+                    }
+                }
+            }
+            // Open a link, or close it with an empty URI. The URI may itself contain semicolons.
+            [b"8", link_params, uri @ ..] => {
+                let uri = uri.join(&b';');
+                let hyperlink = if uri.is_empty() {
+                    None
+                } else {
+                    let id = link_params
+                        .split(|&b| b == b':')
+                        .find_map(|param| param.strip_prefix(b"id="))
+                        .map(|id| String::from_utf8_lossy(id).into_owned());
+                    let uri = String::from_utf8_lossy(&uri).into_owned();
+                    Some(Hyperlink { id, uri })
+                };
+                screen.set_hyperlink(hyperlink);
+            }
+            // Reset indexed colors, or all of them if none are given
+            [b"104"] | [b"104", b""] => screen.reset_palette(),
+            [b"104", indexes @ ..] => {
+                for index in indexes.iter().filter_map(|index| parse_number(index)) {
+                    screen.reset_color(usize::from(index));
+                }
+            }
+            [b"110", ..] => screen.reset_color(NamedColor::Foreground as usize),
+            [b"111", ..] => screen.reset_color(NamedColor::Background as usize),
+            _ => {}
+        }
+    }
 
-    fn csi_dispatch(&mut self, params: &[i64], _intermediates: &[u8], _ignore: bool, c: char) {
-        if c != 'm' {
-            // Not a color "CSI"
+    fn csi_dispatch(&mut self, params: &Params, intermediates: &[u8], ignore: bool, c: char) {
+        self.backspaced = false;
+
+        if ignore {
             return;
         }
 
-        let foreground = PrimaryColors::default().foreground;
-        let indexed_colors = List::from(&Colors::default());
-
-        for attr in attrs_from_sgr_parameters(&params) {
-            if let Some(attr) = attr {
-                let color = match attr {
-                    Attr::Foreground(foreground) => match foreground {
-                        Color::Indexed(index) => Some(indexed_colors[index]),
-                        Color::Named(index) => Some(indexed_colors[index]),
-                        Color::Spec(_) => todo!(),
-                    },
-                    Attr::Reset => Some(foreground),
-                    Attr::Bold
-                    | Attr::Dim
-                    | Attr::Italic
-                    | Attr::Underline
-                    | Attr::BlinkSlow
-                    | Attr::BlinkFast
-                    | Attr::Reverse
-                    | Attr::Hidden
-                    | Attr::Strike
-                    | Attr::CancelBold
-                    | Attr::CancelBoldDim
-                    | Attr::CancelItalic
-                    | Attr::CancelUnderline
-                    | Attr::CancelBlink
-                    | Attr::CancelReverse
-                    | Attr::CancelHidden
-                    | Attr::CancelStrike
-                    | Attr::Background { .. } => None,
-                };
-                if let Some(c) = color {
-                    self.output.push(Token::Color(c));
+        // Missing and zero parameters both take the default
+        let arg = |index: usize, default: usize| match params.iter().nth(index) {
+            Some(&[param, ..]) if param > 0 => param as usize,
+            _ => default,
+        };
+
+        let screen = &mut self.screen;
+
+        match (c, intermediates) {
+            ('m', []) => {
+                for attr in attrs_from_sgr_parameters(&mut params.iter())
+                    .into_iter()
+                    .flatten()
+                {
+                    screen.terminal_attribute(attr);
+                }
+            }
+            ('A', []) => screen.move_up(arg(0, 1)),
+            ('B', []) | ('e', []) => screen.move_down(arg(0, 1)),
+            ('C', []) | ('a', []) => screen.move_forward(arg(0, 1)),
+            ('D', []) => screen.move_backward(arg(0, 1)),
+            ('E', []) => {
+                screen.move_down(arg(0, 1));
+                screen.carriage_return();
+            }
+            ('F', []) => {
+                screen.move_up(arg(0, 1));
+                screen.carriage_return();
+            }
+            ('G', []) | ('`', []) => screen.goto_column(arg(0, 1) - 1),
+            ('H', []) | ('f', []) => screen.goto(arg(0, 1) - 1, arg(1, 1) - 1),
+            ('d', []) => screen.goto_line(arg(0, 1) - 1),
+            ('J', []) | ('J', [b'?']) => screen.erase_in_display(arg(0, 0)),
+            ('K', []) | ('K', [b'?']) => screen.erase_in_line(arg(0, 0)),
+            ('X', []) => screen.erase_chars(arg(0, 1)),
+            ('h', [b'?']) => {
+                for param in params {
+                    self.set_private_mode(param[0], true);
+                }
+            }
+            ('l', [b'?']) => {
+                for param in params {
+                    self.set_private_mode(param[0], false);
+                }
+            }
+            ('s', []) => screen.save_cursor(),
+            ('u', []) => screen.restore_cursor(),
+            ('L', []) => screen.insert_lines(arg(0, 1)),
+            ('M', []) => screen.delete_lines(arg(0, 1)),
+            ('@', []) => screen.insert_chars(arg(0, 1)),
+            ('P', []) => screen.delete_chars(arg(0, 1)),
+            ('S', []) => screen.scroll_up(arg(0, 1)),
+            ('T', []) => screen.scroll_down(arg(0, 1)),
+            ('r', []) => screen.set_scroll_region(arg(0, 1), arg(1, usize::MAX)),
+            ('I', []) => screen.tab_forward(arg(0, 1)),
+            ('Z', []) => screen.tab_backward(arg(0, 1)),
+            ('g', []) => screen.clear_tab_stops(arg(0, 0)),
+            ('b', []) => screen.repeat(arg(0, 1)),
+            _ => {}
+        }
+    }
+
+    fn esc_dispatch(&mut self, intermediates: &[u8], ignore: bool, byte: u8) {
+        self.backspaced = false;
+
+        if ignore {
+            return;
+        }
+
+        let screen = &mut self.screen;
+
+        match (byte, intermediates) {
+            (b'D', []) => screen.linefeed(),
+            (b'E', []) => {
+                screen.carriage_return();
+                screen.linefeed();
+            }
+            (b'M', []) => screen.reverse_index(),
+            (b'H', []) => screen.set_tab_stop(),
+            (b'7', []) => screen.save_cursor(),
+            (b'8', []) => screen.restore_cursor(),
+            // SS2, SS3, LS2 and LS3
+            (b'N', []) => screen.single_shift(CharsetIndex::G2),
+            (b'O', []) => screen.single_shift(CharsetIndex::G3),
+            (b'n', []) => screen.set_active_charset(CharsetIndex::G2),
+            (b'o', []) => screen.set_active_charset(CharsetIndex::G3),
+            (byte, &[intermediate]) => {
+                let index = CharsetIndex::from_intermediate(intermediate);
+                if let (Some(index), Some(charset)) = (index, StandardCharset::from_final(byte)) {
+                    screen.designate_charset(index, charset);
                 }
             }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ansi::{Colors, List, Rgb};
+
+    /// Run `input` through a parser for an 80x24 screen.
+    fn parse_bytes(input: &[u8]) -> Screen {
+        let mut statemachine = vte::Parser::new();
+        let mut parser = Parser {
+            screen: Screen::new(80, 24),
+            overstrike: false,
+            onlcr: true,
+            backspaced: false,
+        };
+        for &byte in input {
+            statemachine.advance(&mut parser, byte);
         }
+        parser.screen
+    }
+
+    #[test]
+    fn reset_palette_keeps_default_colors() {
+        let white = Rgb {
+            r: 0xff,
+            g: 0xff,
+            b: 0xff,
+        };
+        let black = Rgb { r: 0, g: 0, b: 0 };
+
+        let screen =
+            parse_bytes(b"\x1b]11;#ffffff\x07\x1b]10;#000000\x07\x1b]4;1;#000000\x07\x1b]104\x07");
+        let palette = screen.palette();
+
+        assert_eq!(palette[NamedColor::Background], white);
+        assert_eq!(palette[NamedColor::Foreground], black);
+        assert_eq!(palette[NamedColor::BrightForeground], black);
+        assert_eq!(palette[1usize], List::from(&Colors::default())[1usize]);
     }
 
-    fn esc_dispatch(&mut self, _intermediates: &[u8], _ignore: bool, _byte: u8) {}
+    #[test]
+    fn reset_default_colors() {
+        let screen = parse_bytes(b"\x1b]11;#ffffff\x07\x1b]10;#000000\x07\x1b]110\x07\x1b]111\x07");
+        let defaults = List::from(&Colors::default());
+        let palette = screen.palette();
+
+        assert_eq!(
+            palette[NamedColor::Background],
+            defaults[NamedColor::Background]
+        );
+        assert_eq!(
+            palette[NamedColor::Foreground],
+            defaults[NamedColor::Foreground]
+        );
+    }
 }
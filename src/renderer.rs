@@ -1,98 +1,965 @@
-use image::{DynamicImage, Pixel, Rgba};
-use rusttype::{point, Font, Scale};
+use image::{DynamicImage, Pixel, Rgba, RgbaImage};
+use rusttype::{point, Font, PositionedGlyph, Rect, Scale};
 
-use std::fs;
+use crate::ansi::{Color, List, NamedColor, Rgb, DIM_FACTOR};
+use crate::font::FontFamily;
+use crate::screen::{Blink, Buffer, Cell, Frame, Hyperlink, Screen, Script, Style, Underline};
 
-use crate::ansi::PrimaryColors;
-use crate::parser::Token;
+use gif::SetParameter;
 
-pub fn render(tokens: &[Token], font: &str, out: &str) {
-    // Load the font
-    let font_data = fs::read(&font).unwrap();
+use std::convert::TryFrom;
+use std::f32::consts::PI;
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::mem;
+use std::path::Path;
+use std::process;
+use std::str::FromStr;
 
-    // This only succeeds if collection consists of one font
-    let font = Font::try_from_bytes(&font_data).unwrap();
+/// Horizontal shift per pixel of height for synthetic italics, roughly a 12 degree slant.
+const OBLIQUE_SLANT: f32 = 0.2;
+
+/// Size of superscript and subscript glyphs relative to the rest of the text.
+const SCRIPT_SCALE: f32 = 0.6;
+
+/// Whether bold text in one of the first eight colors is drawn in the bright version instead, as
+/// many terminals do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BoldBright {
+    Off,
+    /// Bright colors replace the bold face.
+    BrightOnly,
+    /// Bright colors as well as the bold face.
+    BrightAndBold,
+}
+
+impl FromStr for BoldBright {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(BoldBright::Off),
+            "bright-only" => Ok(BoldBright::BrightOnly),
+            "bright-and-bold" => Ok(BoldBright::BrightAndBold),
+            _ => Err(format!("unknown bold mode: {}", s)),
+        }
+    }
+}
+
+/// How blinking text is drawn in a static image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlinkStyle {
+    Show,
+    Dim,
+    Hide,
+}
+
+impl FromStr for BlinkStyle {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "show" => Ok(BlinkStyle::Show),
+            "dim" => Ok(BlinkStyle::Dim),
+            "hide" => Ok(BlinkStyle::Hide),
+            _ => Err(format!("unknown blink style: {}", s)),
+        }
+    }
+}
+
+/// How each blink rate is drawn in one frame.
+#[derive(Debug, Clone, Copy)]
+struct BlinkPhase {
+    slow: BlinkStyle,
+    fast: BlinkStyle,
+}
+
+impl BlinkPhase {
+    fn style(self, blink: Blink) -> BlinkStyle {
+        match blink {
+            Blink::Slow => self.slow,
+            Blink::Fast => self.fast,
+        }
+    }
+}
+
+pub struct Options {
+    pub buffer: Buffer,
+    pub bold_bright: BoldBright,
+    pub blink: BlinkStyle,
+    /// Underline linked text that isn't already underlined.
+    pub link_underline: bool,
+    /// Draw a title bar showing the window title above the text.
+    pub title_bar: bool,
+}
+
+/// Render the screen to `out`. A `.gif` is animated so blinking text blinks, and an `.html` page
+/// keeps the text and its links; anything else is a static image in the format implied by the
+/// extension.
+pub fn render(screen: &Screen, fonts: &FontFamily, options: &Options, out: &str) {
+    let bold_bright = options.bold_bright;
+    let font = fonts.regular();
 
     // The font size to use
     let scale = Scale::uniform(32.0);
 
+    // How far synthetic bold smears each glyph to the right
+    let embolden_strength = (scale.x / 24.0).round().max(1.0) as i32;
+
+    let mut indexed_colors = *screen.palette();
+
+    // DECSCNM swaps the default colors of the whole screen
+    if screen.reverse_video() {
+        let foreground = indexed_colors[NamedColor::Foreground];
+        indexed_colors[NamedColor::Foreground] = indexed_colors[NamedColor::Background];
+        indexed_colors[NamedColor::Background] = foreground;
+
+        // The bright and dim foregrounds follow, as they do for `Screen::set_color`
+        let foreground = indexed_colors[NamedColor::Foreground];
+        indexed_colors[NamedColor::BrightForeground] = foreground;
+        indexed_colors[NamedColor::DimForeground] = foreground * DIM_FACTOR;
+    }
+
+    let underline_metrics = fonts.underline_metrics(scale);
+    let strikeout_metrics = fonts.strikeout_metrics(scale);
+
+    let v_metrics = font.v_metrics(scale);
     let (glyphs_height, glyphs_width) = {
-        let v_metrics = font.v_metrics(scale);
-        let glyphs_height = (v_metrics.ascent - v_metrics.descent).ceil() as u32;
+        let glyphs_height =
+            (v_metrics.ascent - v_metrics.descent + v_metrics.line_gap).ceil() as u32;
 
-        // Generate a glyph to get the width/height. Because we're using a monospaced font we can
-        // use any char.
-        let glyph = font.glyph('m').scaled(scale).positioned(point(0.0, 0.0));
-        let glyphs_width = glyph.pixel_bounding_box().unwrap().width() as u32;
+        // Because we're using a monospaced font we can use the advance width of any char.
+        let glyphs_width = font
+            .glyph('m')
+            .scaled(scale)
+            .h_metrics()
+            .advance_width
+            .ceil() as u32;
 
         (glyphs_height, glyphs_width)
     };
 
-    let some_random_height_padding = 10;
-
-    let chars_count = tokens
-        .iter()
-        .filter(|t| match t {
-            Token::Char(_) => true,
-            _ => false,
+    // Only the part of the screen that was written to is drawn
+    let rows: Vec<_> = screen
+        .rows(options.buffer)
+        .map(|row| {
+            let len = row.iter().rposition(|c| !c.is_empty()).map_or(0, |i| i + 1);
+            &row[..len]
         })
-        .count();
+        .collect();
+    let rows_count = rows
+        .iter()
+        .rposition(|row| !row.is_empty())
+        .map_or(1, |i| i + 1);
+    let rows = &rows[..rows_count];
+    let columns = rows.iter().map(|row| row.len()).max().unwrap_or(0);
+
+    if Path::new(out).extension() == Some("html".as_ref()) {
+        save_html(rows, screen.hyperlinks(), &indexed_colors, options, out);
+        return;
+    }
 
-    // Create a new RGBA image
     let padding_left = 10;
     let padding_right = 10;
-    let image_width = (glyphs_width * chars_count as u32) + padding_left + padding_right;
-    let image_height = glyphs_height + some_random_height_padding;
-    let mut image = DynamicImage::new_rgba8(image_width, image_height).to_rgba();
-    // Black background
-    for (_, _, p) in image.enumerate_pixels_mut() {
-        *p = Rgba([0, 0, 0, 255]);
-    }
+    let title_bar_height = if options.title_bar { glyphs_height } else { 0 };
+    let padding_top = 5 + title_bar_height;
+    let padding_bottom = 5;
+    let image_width = (glyphs_width * columns as u32) + padding_left + padding_right;
+    let image_height = (glyphs_height * rows_count as u32) + padding_top + padding_bottom;
 
-    let some_random_padding = 28.0;
+    let draw_frame = |blink: BlinkPhase| {
+        // Create a new RGBA image
+        let mut image = DynamicImage::new_rgba8(image_width, image_height).to_rgba();
+        // Default background
+        let background = resolve(Color::Named(NamedColor::Background), &indexed_colors);
+        for (_, _, p) in image.enumerate_pixels_mut() {
+            *p = Rgba([background.r, background.g, background.b, 255]);
+        }
 
-    let mut color = PrimaryColors::default().foreground;
-    let mut x_pos = padding_left;
+        if options.title_bar {
+            let title = screen.title().unwrap_or("");
+            draw_title_bar(&mut image, title_bar_height, title, font, &indexed_colors);
+        }
+
+        // Cell backgrounds are all filled before any glyphs so overhanging glyphs aren't covered
+        for (line, row) in rows.iter().enumerate() {
+            for (column, cell) in row.iter().enumerate() {
+                let style = blink_style(&cell.style, blink);
+                let (_, color) = cell_colors(&style, bold_bright, &indexed_colors);
+                if color == background {
+                    continue;
+                }
 
-    let colors_and_glyphs: Vec<_> = tokens
+                let x_pos = padding_left + column as u32 * glyphs_width;
+                let y_pos = padding_top + line as u32 * glyphs_height;
+                for y in y_pos..y_pos + glyphs_height {
+                    for x in x_pos..x_pos + glyphs_width {
+                        image.put_pixel(x, y, Rgba([color.r, color.g, color.b, 255]));
+                    }
+                }
+            }
+        }
+
+        for (line, row) in rows.iter().enumerate() {
+            let baseline = padding_top + line as u32 * glyphs_height;
+            let baseline = baseline as f32 + v_metrics.ascent;
+
+            for (column, cell) in row.iter().enumerate() {
+                let style = &blink_style(&cell.style, blink);
+                let (fg, _) = cell_colors(style, bold_bright, &indexed_colors);
+
+                let bold = draws_bold(style, bold_bright, &indexed_colors);
+                let face = fonts.face(bold, style.italic);
+
+                let x_pos = padding_left + column as u32 * glyphs_width;
+
+                // Superscripts and subscripts are shrunk, centered and moved off the baseline
+                let (glyph_scale, glyph_baseline) = match style.script {
+                    Some(Script::Super) => {
+                        (scale.y * SCRIPT_SCALE, baseline - v_metrics.ascent * 0.4)
+                    }
+                    Some(Script::Sub) => {
+                        (scale.y * SCRIPT_SCALE, baseline - v_metrics.descent * 0.5)
+                    }
+                    None => (scale.y, baseline),
+                };
+                let glyph = face.font.glyph(cell.c).scaled(Scale::uniform(glyph_scale));
+                let glyph_x = x_pos as f32
+                    + (glyphs_width as f32 - glyph.h_metrics().advance_width).max(0.0) / 2.0;
+                let glyph = glyph.positioned(point(glyph_x, glyph_baseline));
+
+                // Slanted glyphs lean into the next cell, so keep them inside their own
+                let clip = if style.italic {
+                    let y_pos = padding_top + line as u32 * glyphs_height;
+                    Some(Rect {
+                        min: point(x_pos as i32, y_pos as i32),
+                        max: point(
+                            (x_pos + glyphs_width) as i32,
+                            (y_pos + glyphs_height) as i32,
+                        ),
+                    })
+                } else {
+                    None
+                };
+
+                if let Some(mut coverage) = Coverage::rasterize(&glyph) {
+                    if face.embolden {
+                        coverage.embolden(embolden_strength);
+                    }
+                    if face.oblique {
+                        coverage.oblique(glyph_baseline);
+                    }
+                    coverage.draw(&mut image, fg, clip);
+                }
+
+                // Links without an underline of their own get a dashed one, as in VTE
+                let underline = match style.underline {
+                    Underline::None if options.link_underline && cell.hyperlink.is_some() => {
+                        Underline::Dashed
+                    }
+                    underline => underline,
+                };
+                if underline != Underline::None {
+                    let color = match style.underline_color {
+                        Some(color) if !style.hidden => resolve(color, &indexed_colors),
+                        _ => fg,
+                    };
+                    let top = baseline - underline_metrics.position;
+                    draw_underline(
+                        &mut image,
+                        underline,
+                        x_pos,
+                        glyphs_width,
+                        top,
+                        underline_metrics.thickness,
+                        color,
+                    );
+                }
+
+                if style.strike {
+                    let top = baseline - strikeout_metrics.position;
+                    let thickness = strikeout_metrics.thickness.round().max(1.0) as u32;
+                    fill_rect(
+                        &mut image,
+                        x_pos,
+                        top.round() as u32,
+                        glyphs_width,
+                        thickness,
+                        fg,
+                    );
+                }
+                if style.overline {
+                    let top = padding_top + line as u32 * glyphs_height;
+                    let thickness = underline_metrics.thickness.round().max(1.0) as u32;
+                    fill_rect(&mut image, x_pos, top, glyphs_width, thickness, fg);
+                }
+
+                if let Some(frame) = style.frame {
+                    let y_pos = padding_top + line as u32 * glyphs_height;
+                    let thickness = underline_metrics.thickness.round().max(1.0) as u32;
+                    let cell_rect = Rect {
+                        min: point(x_pos, y_pos),
+                        max: point(x_pos + glyphs_width, y_pos + glyphs_height),
+                    };
+
+                    match frame {
+                        Frame::Framed => {
+                            // Runs of framed cells share one frame
+                            let framed = |cell: Option<&Cell>| {
+                                cell.is_some_and(|cell| cell.style.frame == Some(Frame::Framed))
+                            };
+                            let open_left = framed(column.checked_sub(1).and_then(|c| row.get(c)));
+                            let open_right = framed(row.get(column + 1));
+                            draw_frame_edges(
+                                &mut image, cell_rect, thickness, open_left, open_right, fg,
+                            );
+                        }
+                        Frame::Encircled => {
+                            draw_ellipse(&mut image, cell_rect, underline_metrics.thickness, fg)
+                        }
+                    }
+                }
+            }
+        }
+
+        image
+    };
+
+    let animated = Path::new(out).extension() == Some("gif".as_ref());
+    if !animated {
+        let image = draw_frame(BlinkPhase {
+            slow: options.blink,
+            fast: options.blink,
+        });
+
+        // Save the image in the format given by its extension. The `image` crate can't write text
+        // chunks, so PNGs with a title are encoded directly.
+        match screen.title() {
+            Some(title) if Path::new(out).extension() == Some("png".as_ref()) => {
+                save_png(&image, title, out)
+            }
+            _ => image.save(&out).unwrap(),
+        }
+        return;
+    }
+
+    // Slow blinks toggle every 400ms and fast ones every 200ms, so four frames cover both
+    let blinks: Vec<_> = rows
         .iter()
-        .filter_map(|token| match token {
-            Token::Color(c) => {
-                color = *c;
-                None
+        .flat_map(|row| row.iter())
+        .filter_map(|cell| cell.style.blink)
+        .collect();
+    let phases: &[(BlinkStyle, BlinkStyle)] = if blinks.contains(&Blink::Fast) {
+        &[
+            (BlinkStyle::Show, BlinkStyle::Show),
+            (BlinkStyle::Show, BlinkStyle::Hide),
+            (BlinkStyle::Hide, BlinkStyle::Show),
+            (BlinkStyle::Hide, BlinkStyle::Hide),
+        ]
+    } else if blinks.contains(&Blink::Slow) {
+        &[
+            (BlinkStyle::Show, BlinkStyle::Show),
+            (BlinkStyle::Hide, BlinkStyle::Show),
+        ]
+    } else {
+        &[(BlinkStyle::Show, BlinkStyle::Show)]
+    };
+    let delay = 80 / phases.len() as u16;
+
+    let frames = phases
+        .iter()
+        .map(|&(slow, fast)| (draw_frame(BlinkPhase { slow, fast }), delay));
+    save_gif(frames, image_width, image_height, out);
+}
+
+/// Save a PNG with the window title in its `Title` text chunk.
+fn save_png(image: &RgbaImage, title: &str, out: &str) {
+    let file = File::create(out).unwrap();
+    let mut encoder = png::Encoder::new(BufWriter::new(file), image.width(), image.height());
+    encoder.set_color(png::ColorType::RGBA);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().unwrap();
+
+    // tEXt only holds Latin-1, anything else goes in an uncompressed iTXt chunk
+    if title.chars().all(|c| (c as u32) < 0x100) {
+        let mut data = b"Title\0".to_vec();
+        data.extend(title.chars().map(|c| c as u8));
+        writer.write_chunk(*b"tEXt", &data).unwrap();
+    } else {
+        // No compression, language tag or translated keyword
+        let mut data = b"Title\0\0\0\0\0".to_vec();
+        data.extend(title.as_bytes());
+        writer.write_chunk(*b"iTXt", &data).unwrap();
+    }
+
+    writer.write_image_data(image).unwrap();
+}
+
+/// Draw a window title bar across the top of the image with the title centered in it, shortened
+/// with an ellipsis if it doesn't fit.
+fn draw_title_bar(
+    image: &mut RgbaImage,
+    height: u32,
+    title: &str,
+    font: &Font<'static>,
+    indexed_colors: &List,
+) {
+    let background = resolve(Color::Named(NamedColor::Background), indexed_colors);
+    let foreground = resolve(Color::Named(NamedColor::Foreground), indexed_colors);
+
+    // Shading towards the foreground sets the bar apart on light and dark backgrounds alike
+    let mix = |bg: u8, fg: u8| (f32::from(bg) * 0.85 + f32::from(fg) * 0.15) as u8;
+    let bar = Rgb {
+        r: mix(background.r, foreground.r),
+        g: mix(background.g, foreground.g),
+        b: mix(background.b, foreground.b),
+    };
+    fill_rect(image, 0, 0, image.width(), height, bar);
+
+    let scale = Scale::uniform(height as f32 * 0.6);
+    let width = |text: &str| {
+        font.layout(text, scale, point(0.0, 0.0))
+            .last()
+            .map_or(0.0, |glyph| {
+                glyph.position().x + glyph.unpositioned().h_metrics().advance_width
+            })
+    };
+
+    let max_width = image.width().saturating_sub(20) as f32;
+    let mut title = title.to_string();
+    if width(&title) > max_width {
+        while !title.is_empty() && width(&format!("{}…", title)) > max_width {
+            title.pop();
+        }
+        title.push('…');
+    }
+
+    let v_metrics = font.v_metrics(scale);
+    let x = (image.width() as f32 - width(&title)) / 2.0;
+    let baseline = (height as f32 + v_metrics.ascent + v_metrics.descent) / 2.0;
+    for glyph in font.layout(&title, scale, point(x, baseline)) {
+        if let Some(coverage) = Coverage::rasterize(&glyph) {
+            coverage.draw(image, foreground, None);
+        }
+    }
+}
+
+/// Save an endlessly looping GIF. Frame delays are in hundredths of a second.
+/// Exits with an error if the image is too large for a GIF.
+fn save_gif(frames: impl Iterator<Item = (RgbaImage, u16)>, width: u32, height: u32, out: &str) {
+    let (width, height) = match gif_size(width, height) {
+        Ok(size) => size,
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(1);
+        }
+    };
+
+    let file = File::create(out).unwrap();
+    let mut encoder = gif::Encoder::new(file, width, height, &[]).unwrap();
+    encoder.set(gif::Repeat::Infinite).unwrap();
+
+    for (image, delay) in frames {
+        let mut pixels = image.into_raw();
+        let mut frame = gif::Frame::from_rgba_speed(width, height, &mut pixels, 10);
+        frame.delay = delay;
+        encoder.write_frame(&frame).unwrap();
+    }
+}
+
+/// The size of an image as GIF dimensions, which are limited to 16 bits.
+fn gif_size(width: u32, height: u32) -> Result<(u16, u16), String> {
+    match (u16::try_from(width), u16::try_from(height)) {
+        (Ok(width), Ok(height)) => Ok((width, height)),
+        _ => Err(format!(
+            "Image is {}x{} pixels, too large for a GIF (at most {}x{}); save it as a PNG instead",
+            width,
+            height,
+            u16::MAX,
+            u16::MAX
+        )),
+    }
+}
+
+/// Save the rows as a page of preformatted text, with links kept as `<a href>` elements.
+fn save_html(
+    rows: &[&[Cell]],
+    hyperlinks: &[Hyperlink],
+    indexed_colors: &List,
+    options: &Options,
+    out: &str,
+) {
+    let blink = BlinkPhase {
+        slow: options.blink,
+        fast: options.blink,
+    };
+    let background = resolve(Color::Named(NamedColor::Background), indexed_colors);
+    let foreground = resolve(Color::Named(NamedColor::Foreground), indexed_colors);
+
+    let mut html = String::from("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n");
+    html.push_str("<style>a { color: inherit; }</style>\n</head>\n<body>\n");
+    html.push_str(&format!(
+        "<pre style=\"background: {}; color: {}; padding: 5px 10px\">",
+        css_color(background),
+        css_color(foreground)
+    ));
+
+    for row in rows {
+        let mut link = None;
+
+        for run in row.chunk_by(|a, b| a.style == b.style && a.hyperlink == b.hyperlink) {
+            // Links with other schemes, like `javascript:`, are left as plain text
+            let run_link = run[0]
+                .hyperlink
+                .filter(|&index| is_safe_link(&hyperlinks[index].uri));
+            if run_link != link {
+                if link.is_some() {
+                    html.push_str("</a>");
+                }
+                if let Some(index) = run_link {
+                    let href = escape_html(&hyperlinks[index].uri);
+                    html.push_str(&format!("<a href=\"{}\">", href));
+                }
+                link = run_link;
+            }
+
+            let style = blink_style(&run[0].style, blink);
+
+            // Concealed text mustn't be recoverable by selecting it
+            let text: String = if style.hidden {
+                " ".repeat(run.len())
+            } else {
+                run.iter().map(|cell| cell.c).collect()
+            };
+            let (fg, bg) = cell_colors(&style, options.bold_bright, indexed_colors);
+
+            let mut css = Vec::new();
+            if fg != foreground {
+                css.push(format!("color: {}", css_color(fg)));
+            }
+            if bg != background {
+                css.push(format!("background: {}", css_color(bg)));
+            }
+            if draws_bold(&style, options.bold_bright, indexed_colors) {
+                css.push("font-weight: bold".to_string());
+            }
+            if style.italic {
+                css.push("font-style: italic".to_string());
             }
-            Token::Char(c) => {
-                let glyph = font
-                    .glyph(*c)
-                    .scaled(scale)
-                    .positioned(point(x_pos as f32, some_random_padding));
 
-                x_pos += glyphs_width;
+            let mut lines = Vec::new();
+            if style.underline != Underline::None {
+                lines.push("underline");
+            }
+            if style.strike {
+                lines.push("line-through");
+            }
+            if style.overline {
+                lines.push("overline");
+            }
+            if !lines.is_empty() {
+                css.push(format!("text-decoration: {}", lines.join(" ")));
+            }
+            let line_style = match style.underline {
+                Underline::Double => Some("double"),
+                Underline::Curly => Some("wavy"),
+                Underline::Dotted => Some("dotted"),
+                Underline::Dashed => Some("dashed"),
+                Underline::None | Underline::Single => None,
+            };
+            if let Some(line_style) = line_style {
+                css.push(format!("text-decoration-style: {}", line_style));
+            }
+            if let Some(color) = style.underline_color.filter(|_| !style.hidden) {
+                let color = resolve(color, indexed_colors);
+                css.push(format!("text-decoration-color: {}", css_color(color)));
+            }
+
+            match style.frame {
+                Some(Frame::Framed) => css.push("outline: 1px solid".to_string()),
+                Some(Frame::Encircled) => {
+                    css.push("outline: 1px solid; border-radius: 50%".to_string())
+                }
+                None => {}
+            }
+            match style.script {
+                Some(Script::Super) => {
+                    css.push("vertical-align: super; font-size: 60%".to_string())
+                }
+                Some(Script::Sub) => css.push("vertical-align: sub; font-size: 60%".to_string()),
+                None => {}
+            }
+
+            if css.is_empty() {
+                html.push_str(&escape_html(&text));
+            } else {
+                html.push_str(&format!(
+                    "<span style=\"{}\">{}</span>",
+                    css.join("; "),
+                    escape_html(&text)
+                ));
+            }
+        }
+
+        if link.is_some() {
+            html.push_str("</a>");
+        }
+        html.push('\n');
+    }
+
+    html.push_str("</pre>\n</body>\n</html>\n");
+    fs::write(out, html).unwrap();
+}
+
+/// Whether a link's scheme is one that's safe to put in a page.
+fn is_safe_link(uri: &str) -> bool {
+    match uri.split_once(':') {
+        Some((scheme, _)) => ["http", "https", "mailto", "file"]
+            .iter()
+            .any(|safe| scheme.eq_ignore_ascii_case(safe)),
+        None => false,
+    }
+}
+
+fn css_color(color: Rgb) -> String {
+    format!("#{:02x}{:02x}{:02x}", color.r, color.g, color.b)
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Apply how blinking text is drawn in this frame to a cell's style.
+fn blink_style(style: &Style, blink: BlinkPhase) -> Style {
+    let mut style = *style;
+    match style.blink.map(|rate| blink.style(rate)) {
+        Some(BlinkStyle::Dim) => style.dim = true,
+        Some(BlinkStyle::Hide) => style.hidden = true,
+        Some(BlinkStyle::Show) | None => {}
+    }
+    style
+}
 
-                Some((color, glyph))
+/// Look up the RGB value of a color in the palette.
+fn resolve(color: Color, indexed_colors: &List) -> Rgb {
+    match color {
+        Color::Indexed(index) => indexed_colors[index],
+        Color::Named(index) => indexed_colors[index],
+        Color::Spec(rgb) => rgb,
+    }
+}
+
+/// Whether a cell is drawn with a bold face. With `BrightOnly` bold text that was brightened is
+/// shown by its color alone, but text whose color has no bright version stays bold.
+fn draws_bold(style: &Style, bold_bright: BoldBright, indexed_colors: &List) -> bool {
+    let brightened =
+        || resolve_fg(style, true, indexed_colors) != resolve_fg(style, false, indexed_colors);
+
+    style.bold && (bold_bright != BoldBright::BrightOnly || !brightened())
+}
+
+/// The foreground and background colors of a cell, after reverse video and concealment.
+fn cell_colors(style: &Style, bold_bright: BoldBright, indexed_colors: &List) -> (Rgb, Rgb) {
+    let mut fg = resolve_fg(style, bold_bright != BoldBright::Off, indexed_colors);
+    let mut bg = resolve(style.bg, indexed_colors);
+
+    if style.reverse {
+        mem::swap(&mut fg, &mut bg);
+    }
+
+    // Concealed text is drawn in the background color
+    if style.hidden {
+        fg = bg;
+    }
+
+    (fg, bg)
+}
+
+/// Draw an underline across a cell `width` pixels wide starting at `x`, with the top of the line
+/// at `top`.
+fn draw_underline(
+    image: &mut RgbaImage,
+    underline: Underline,
+    x: u32,
+    width: u32,
+    top: f32,
+    thickness: f32,
+    color: Rgb,
+) {
+    let thickness_px = thickness.round().max(1.0) as u32;
+    let top_px = top.round().max(0.0) as u32;
+
+    match underline {
+        Underline::None => {}
+        Underline::Single => fill_rect(image, x, top_px, width, thickness_px, color),
+        Underline::Double => {
+            fill_rect(image, x, top_px, width, thickness_px, color);
+            fill_rect(
+                image,
+                x,
+                top_px + thickness_px * 2,
+                width,
+                thickness_px,
+                color,
+            );
+        }
+        Underline::Dotted => {
+            for dot_x in (x..x + width).filter(|dot_x| (dot_x / thickness_px).is_multiple_of(2)) {
+                fill_rect(image, dot_x, top_px, 1, thickness_px, color);
             }
+        }
+        Underline::Dashed => {
+            let dash = (width / 2).max(1);
+            fill_rect(image, x, top_px, dash, thickness_px, color);
+        }
+        Underline::Curly => {
+            // One full wave per cell, so waves join up across neighbouring cells
+            let amplitude = thickness.max(1.5);
+            let center = top + thickness / 2.0;
+
+            for wave_x in x..x + width {
+                let phase = (wave_x - x) as f32 + 0.5;
+                let wave_y = center + amplitude * (2.0 * PI * phase / width as f32).sin();
+
+                let min_y = (wave_y - thickness).floor().max(0.0) as u32;
+                let max_y = (wave_y + thickness).ceil() as u32;
+                for y in min_y..=max_y {
+                    let distance = (y as f32 + 0.5 - wave_y).abs();
+                    let v = (thickness / 2.0 + 0.5 - distance).clamp(0.0, 1.0);
+                    blend_pixel(image, wave_x, y, color, v);
+                }
+            }
+        }
+    }
+}
+
+/// Draw the outline of a frame around a cell, leaving the sides open where it continues into a
+/// neighbouring framed cell.
+fn draw_frame_edges(
+    image: &mut RgbaImage,
+    cell: Rect<u32>,
+    thickness: u32,
+    open_left: bool,
+    open_right: bool,
+    color: Rgb,
+) {
+    let width = cell.width();
+    let height = cell.height();
+
+    fill_rect(image, cell.min.x, cell.min.y, width, thickness, color);
+    fill_rect(
+        image,
+        cell.min.x,
+        cell.max.y - thickness,
+        width,
+        thickness,
+        color,
+    );
+    if !open_left {
+        fill_rect(image, cell.min.x, cell.min.y, thickness, height, color);
+    }
+    if !open_right {
+        fill_rect(
+            image,
+            cell.max.x - thickness,
+            cell.min.y,
+            thickness,
+            height,
+            color,
+        );
+    }
+}
+
+/// Draw an anti-aliased ellipse just inside a cell.
+fn draw_ellipse(image: &mut RgbaImage, cell: Rect<u32>, thickness: f32, color: Rgb) {
+    let center_x = (cell.min.x + cell.max.x) as f32 / 2.0;
+    let center_y = (cell.min.y + cell.max.y) as f32 / 2.0;
+    let radius_x = cell.width() as f32 / 2.0 - thickness / 2.0;
+    let radius_y = cell.height() as f32 / 2.0 - thickness / 2.0;
+
+    for y in cell.min.y..cell.max.y {
+        for x in cell.min.x..cell.max.x {
+            let dx = (x as f32 + 0.5 - center_x) / radius_x;
+            let dy = (y as f32 + 0.5 - center_y) / radius_y;
+
+            // Approximate the distance in pixels from the outline
+            let distance = ((dx * dx + dy * dy).sqrt() - 1.0).abs() * radius_x.min(radius_y);
+            let v = (thickness / 2.0 + 0.5 - distance).clamp(0.0, 1.0);
+            if v > 0.0 {
+                blend_pixel(image, x, y, color, v);
+            }
+        }
+    }
+}
+
+fn fill_rect(image: &mut RgbaImage, x: u32, y: u32, width: u32, height: u32, color: Rgb) {
+    for y in y..y + height {
+        for x in x..x + width {
+            blend_pixel(image, x, y, color, 1.0);
+        }
+    }
+}
+
+/// Blend `color` over a pixel with the given coverage, ignoring pixels outside the image.
+fn blend_pixel(image: &mut RgbaImage, x: u32, y: u32, color: Rgb, coverage: f32) {
+    if x >= image.width() || y >= image.height() {
+        return;
+    }
+
+    let color = Rgba([color.r, color.g, color.b, (coverage * 255.0) as u8]);
+    image.get_pixel_mut(x, y).blend(&color);
+}
+
+/// Look up the RGB value of a cell's foreground, using the dim colors for faint text and
+/// optionally the bright colors for bold text, like Alacritty does.
+fn resolve_fg(style: &Style, bold_bright: bool, indexed_colors: &List) -> Rgb {
+    let bright = bold_bright && style.bold;
+
+    match (style.fg, bright, style.dim) {
+        (Color::Spec(rgb), _, true) => rgb * DIM_FACTOR,
+        // There's no configured bright foreground, so bold doesn't change the dim foreground
+        (Color::Named(NamedColor::Foreground), true, true) => {
+            indexed_colors[NamedColor::DimForeground]
+        }
+        (Color::Named(color), true, false) => indexed_colors[color.to_bright()],
+        (Color::Named(color), false, true) => indexed_colors[color.to_dim()],
+        (Color::Indexed(index @ 0..=7), true, false) => indexed_colors[index + 8],
+        (Color::Indexed(index @ 0..=7), false, true) => {
+            indexed_colors[NamedColor::DimBlack as usize + index as usize]
+        }
+        (Color::Indexed(index @ 8..=15), false, true) => indexed_colors[index - 8],
+        // The cube and gray ramp have no dim variants
+        (Color::Indexed(index @ 16..=255), _, true) => indexed_colors[index] * DIM_FACTOR,
+        (color, _, _) => resolve(color, indexed_colors),
+    }
+}
+
+/// The anti-aliased coverage of a rasterised glyph, positioned in image coordinates.
+struct Coverage {
+    x: i32,
+    y: i32,
+    width: usize,
+    height: usize,
+    alpha: Vec<f32>,
+}
+
+impl Coverage {
+    fn rasterize(glyph: &PositionedGlyph) -> Option<Self> {
+        let bounding_box = glyph.pixel_bounding_box()?;
+        let width = bounding_box.width() as usize;
+        let height = bounding_box.height() as usize;
+
+        let mut alpha = vec![0.0; width * height];
+        glyph.draw(|x, y, v| alpha[y as usize * width + x as usize] = v);
+
+        Some(Coverage {
+            x: bounding_box.min.x,
+            y: bounding_box.min.y,
+            width,
+            height,
+            alpha,
         })
-        .collect();
+    }
+
+    /// Thicken the glyph by overlaying copies of it shifted up to `strength` pixels right.
+    fn embolden(&mut self, strength: i32) {
+        let strength = strength as usize;
+        let width = self.width + strength;
+        let mut alpha = vec![0.0f32; width * self.height];
+
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let v = self.alpha[y * self.width + x];
+                for offset in 0..=strength {
+                    let target = &mut alpha[y * width + x + offset];
+                    *target = target.max(v);
+                }
+            }
+        }
+
+        self.width = width;
+        self.alpha = alpha;
+    }
+
+    /// Slant the glyph to the right by shearing each row in proportion to its height above the
+    /// baseline.
+    fn oblique(&mut self, baseline: f32) {
+        let shift = |y: usize| (baseline - (self.y + y as i32) as f32 - 0.5) * OBLIQUE_SLANT;
+        let min = shift(self.height - 1).floor() as i32;
+        let max = shift(0).ceil() as i32;
+
+        let width = self.width + (max - min) as usize + 1;
+        let mut alpha = vec![0.0f32; width * self.height];
+
+        for y in 0..self.height {
+            // Split fractional shifts between neighbouring pixels
+            let shift = shift(y) - min as f32;
+            let whole = shift.floor() as usize;
+            let fraction = shift - whole as f32;
+
+            for x in 0..self.width {
+                let v = self.alpha[y * self.width + x];
+                alpha[y * width + x + whole] += v * (1.0 - fraction);
+                alpha[y * width + x + whole + 1] += v * fraction;
+            }
+        }
+
+        self.x += min;
+        self.width = width;
+        self.alpha = alpha;
+    }
+
+    fn draw(&self, image: &mut RgbaImage, color: Rgb, clip: Option<Rect<i32>>) {
+        let image_bounds = Rect {
+            min: point(0, 0),
+            max: point(image.width() as i32, image.height() as i32),
+        };
+        let clip = clip.unwrap_or(image_bounds);
+
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let v = self.alpha[y * self.width + x].min(1.0);
+                let image_x = self.x + x as i32;
+                let image_y = self.y + y as i32;
+
+                // Glyphs can overhang their cell at the edges of the image
+                if image_x < clip.min.x.max(image_bounds.min.x)
+                    || image_y < clip.min.y.max(image_bounds.min.y)
+                    || image_x >= clip.max.x.min(image_bounds.max.x)
+                    || image_y >= clip.max.y.min(image_bounds.max.y)
+                {
+                    continue;
+                }
 
-    for (color, glyph) in colors_and_glyphs {
-        if let Some(bounding_box) = glyph.pixel_bounding_box() {
-            // Draw the glyph into the image per-pixel by using the draw closure
-            glyph.draw(|x, y, v| {
                 // Turn the coverage into an alpha value
                 let color = Rgba([color.r, color.g, color.b, (v * 255.0) as u8]);
+                image
+                    .get_pixel_mut(image_x as u32, image_y as u32)
+                    .blend(&color);
+            }
+        }
+    }
+}
 
-                // Offset the position by the glyph bounding box
-                let x = x + bounding_box.min.x as u32;
-                let y = y + bounding_box.min.y as u32;
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-                image.get_pixel_mut(x, y).blend(&color);
+    #[test]
+    fn gif_size_fits() {
+        assert_eq!(gif_size(800, 65535), Ok((800, 65535)));
+    }
 
-                // For debugging bounding boxes:
-                // image.put_pixel(x, y, color);
-            });
-        }
+    #[test]
+    fn gif_size_too_tall() {
+        assert!(gif_size(800, 65536).is_err());
     }
 
-    // Save the image to a png file
-    image.save(&out).unwrap();
+    #[test]
+    fn gif_size_too_wide() {
+        assert!(gif_size(70000, 100).is_err());
+    }
 }
@@ -0,0 +1,893 @@
+use crate::ansi::{
+    Attr, CharsetIndex, Color, Colors, List, NamedColor, Rgb, StandardCharset, DIM_FACTOR,
+};
+use std::iter;
+use std::mem;
+use std::ops::Range;
+use std::str::FromStr;
+
+/// Everything about how a cell is drawn other than the character itself.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Style {
+    pub fg: Color,
+    pub bg: Color,
+    pub bold: bool,
+    pub dim: bool,
+    pub italic: bool,
+    pub underline: Underline,
+    /// Color of underlines; `None` uses the foreground color.
+    pub underline_color: Option<Color>,
+    pub blink: Option<Blink>,
+    pub reverse: bool,
+    pub hidden: bool,
+    pub strike: bool,
+    pub overline: bool,
+    pub frame: Option<Frame>,
+    pub script: Option<Script>,
+    /// Requested by SGR 26. Cells keep their fixed width, so with a monospaced font this has no
+    /// visible effect.
+    pub proportional: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Underline {
+    None,
+    Single,
+    Double,
+    Curly,
+    Dotted,
+    Dashed,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Frame {
+    Framed,
+    Encircled,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Script {
+    Super,
+    Sub,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Blink {
+    Slow,
+    Fast,
+}
+
+impl Default for Style {
+    fn default() -> Self {
+        Style {
+            fg: Color::Named(NamedColor::Foreground),
+            bg: Color::Named(NamedColor::Background),
+            bold: false,
+            dim: false,
+            italic: false,
+            underline: Underline::None,
+            underline_color: None,
+            blink: None,
+            reverse: false,
+            hidden: false,
+            strike: false,
+            overline: false,
+            frame: None,
+            script: None,
+            proportional: false,
+        }
+    }
+}
+
+impl Style {
+    /// Apply a single SGR attribute.
+    pub fn apply(&mut self, attr: Attr) {
+        match attr {
+            Attr::Reset => *self = Style::default(),
+            Attr::Bold => self.bold = true,
+            Attr::Dim => self.dim = true,
+            Attr::Italic => self.italic = true,
+            Attr::Underline => self.underline = Underline::Single,
+            Attr::DoubleUnderline => self.underline = Underline::Double,
+            Attr::Undercurl => self.underline = Underline::Curly,
+            Attr::DottedUnderline => self.underline = Underline::Dotted,
+            Attr::DashedUnderline => self.underline = Underline::Dashed,
+            Attr::BlinkSlow => self.blink = Some(Blink::Slow),
+            Attr::BlinkFast => self.blink = Some(Blink::Fast),
+            Attr::Reverse => self.reverse = true,
+            Attr::Hidden => self.hidden = true,
+            Attr::Strike => self.strike = true,
+            Attr::Overline => self.overline = true,
+            Attr::Framed => self.frame = Some(Frame::Framed),
+            Attr::Encircled => self.frame = Some(Frame::Encircled),
+            Attr::Superscript => self.script = Some(Script::Super),
+            Attr::Subscript => self.script = Some(Script::Sub),
+            Attr::ProportionalSpacing => self.proportional = true,
+            Attr::CancelBoldDim => {
+                self.bold = false;
+                self.dim = false;
+            }
+            Attr::CancelItalic => self.italic = false,
+            Attr::CancelUnderline => self.underline = Underline::None,
+            Attr::CancelBlink => self.blink = None,
+            Attr::CancelReverse => self.reverse = false,
+            Attr::CancelHidden => self.hidden = false,
+            Attr::CancelStrike => self.strike = false,
+            Attr::CancelOverline => self.overline = false,
+            Attr::CancelFramedEncircled => self.frame = None,
+            Attr::CancelSuperSubscript => self.script = None,
+            Attr::CancelProportionalSpacing => self.proportional = false,
+            Attr::Foreground(color) => self.fg = color,
+            Attr::Background(color) => self.bg = color,
+            Attr::UnderlineColor(color) => self.underline_color = color,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cell {
+    pub c: char,
+    pub style: Style,
+    /// Index of the cell's link in the screen's hyperlinks.
+    pub hyperlink: Option<usize>,
+}
+
+impl Default for Cell {
+    fn default() -> Self {
+        Cell {
+            c: ' ',
+            style: Style::default(),
+            hyperlink: None,
+        }
+    }
+}
+
+impl Cell {
+    /// Whether the cell would draw nothing on the default background.
+    pub fn is_empty(&self) -> bool {
+        let style = &self.style;
+
+        self.c == ' '
+            && style.bg == Color::Named(NamedColor::Background)
+            && !style.reverse
+            && style.underline == Underline::None
+            && !style.strike
+            && !style.overline
+            && style.frame.is_none()
+    }
+}
+
+pub type Row = Vec<Cell>;
+
+/// A link set with OSC 8.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hyperlink {
+    /// Cells with the same id and URI belong to one link even if they aren't next to each other.
+    pub id: Option<String>,
+    pub uri: String,
+}
+
+/// Distance between the default tab stops.
+const TAB_WIDTH: usize = 8;
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Cursor {
+    line: usize,
+    column: usize,
+    /// Style given to printed characters.
+    style: Style,
+    /// Character sets designated as G0 to G3.
+    charsets: [StandardCharset; 4],
+    /// Set shifted in with SI, SO, LS2 or LS3.
+    active_charset: CharsetIndex,
+    /// Set when a character was printed in the last column; the next printable character wraps
+    /// onto a new line first.
+    input_needs_wrap: bool,
+}
+
+/// Which of the screen buffers to render.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Buffer {
+    Primary,
+    Alternate,
+    /// Whichever buffer was in use at the end of the input.
+    Active,
+}
+
+impl FromStr for Buffer {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "primary" => Ok(Buffer::Primary),
+            "alternate" => Ok(Buffer::Alternate),
+            "active" => Ok(Buffer::Active),
+            _ => Err(format!("unknown screen buffer: {}", s)),
+        }
+    }
+}
+
+/// A virtual terminal screen.
+///
+/// The visible grid has a fixed size. Lines scrolled off the top are kept in `history` so the
+/// full output can be rendered.
+#[derive(Debug)]
+pub struct Screen {
+    columns: usize,
+    lines: usize,
+    /// Lines between the top and bottom margins set by DECSTBM.
+    scroll_region: Range<usize>,
+    history: Vec<Row>,
+    grid: Vec<Row>,
+    /// The inactive buffer; swapped with `grid` when switching screens.
+    inactive_grid: Vec<Row>,
+    alt_active: bool,
+    /// DECSCNM, which swaps the default foreground and background of the whole screen.
+    reverse_video: bool,
+    /// Colors in effect, as changed by OSC 4, 10 and 11.
+    palette: List,
+    /// Every link that was opened; cells refer to them by index.
+    hyperlinks: Vec<Hyperlink>,
+    /// Link given to printed characters.
+    hyperlink: Option<usize>,
+    /// Window title set by OSC 0 or 2.
+    title: Option<String>,
+    cursor: Cursor,
+    /// Cursor saved by DECSC in the active buffer.
+    saved_cursor: Cursor,
+    /// Cursor saved in the inactive buffer; each buffer has its own, like in xterm.
+    inactive_saved_cursor: Cursor,
+    /// Columns with a tab stop set.
+    tabs: Vec<bool>,
+    /// Set used for the next printed character only, after SS2 or SS3.
+    single_shift: Option<CharsetIndex>,
+    /// Last printed character, repeated by REP.
+    last_char: Option<char>,
+}
+
+impl Screen {
+    pub fn new(columns: usize, lines: usize) -> Self {
+        let columns = columns.max(1);
+        let lines = lines.max(1);
+
+        Screen {
+            columns,
+            lines,
+            scroll_region: 0..lines,
+            history: Vec::new(),
+            grid: vec![vec![Cell::default(); columns]; lines],
+            inactive_grid: vec![vec![Cell::default(); columns]; lines],
+            alt_active: false,
+            reverse_video: false,
+            palette: List::from(&Colors::default()),
+            hyperlinks: Vec::new(),
+            hyperlink: None,
+            title: None,
+            cursor: Cursor::default(),
+            saved_cursor: Cursor::default(),
+            inactive_saved_cursor: Cursor::default(),
+            tabs: (0..columns).map(|column| column % TAB_WIDTH == 0).collect(),
+            single_shift: None,
+            last_char: None,
+        }
+    }
+
+    /// All rows of a buffer. The primary buffer includes its history first.
+    pub fn rows(&self, buffer: Buffer) -> impl Iterator<Item = &Row> {
+        let (primary, alternate) = if self.alt_active {
+            (&self.inactive_grid, &self.grid)
+        } else {
+            (&self.grid, &self.inactive_grid)
+        };
+
+        let (history, grid): (&[Row], _) = match buffer {
+            Buffer::Primary => (&self.history, primary),
+            Buffer::Alternate => (&[], alternate),
+            Buffer::Active if self.alt_active => (&[], alternate),
+            Buffer::Active => (&self.history, primary),
+        };
+
+        history.iter().chain(grid.iter())
+    }
+
+    pub fn reverse_video(&self) -> bool {
+        self.reverse_video
+    }
+
+    pub fn set_reverse_video(&mut self, enable: bool) {
+        self.reverse_video = enable;
+    }
+
+    pub fn palette(&self) -> &List {
+        &self.palette
+    }
+
+    /// Change a palette entry. The dim and bright variants derived from it follow the change.
+    pub fn set_color(&mut self, index: usize, color: Rgb) {
+        self.palette[index] = color;
+
+        if index < 8 {
+            self.palette[NamedColor::DimBlack as usize + index] = color * DIM_FACTOR;
+        } else if index == NamedColor::Foreground as usize {
+            self.palette[NamedColor::BrightForeground] = color;
+            self.palette[NamedColor::DimForeground] = color * DIM_FACTOR;
+        }
+    }
+
+    /// Restore a palette entry to its default.
+    pub fn reset_color(&mut self, index: usize) {
+        let default = List::from(&Colors::default());
+        self.set_color(index, default[index]);
+    }
+
+    /// Restore all 256 indexed colors to their defaults. The default foreground and background
+    /// are left alone; they're reset separately.
+    pub fn reset_palette(&mut self) {
+        let defaults = List::from(&Colors::default());
+        for index in 0..256 {
+            self.set_color(index, defaults[index]);
+        }
+    }
+
+    pub fn title(&self) -> Option<&str> {
+        self.title.as_deref()
+    }
+
+    pub fn set_title(&mut self, title: String) {
+        self.title = Some(title);
+    }
+
+    pub fn hyperlinks(&self) -> &[Hyperlink] {
+        &self.hyperlinks
+    }
+
+    /// Start linking printed characters to `hyperlink`, or stop with `None`.
+    pub fn set_hyperlink(&mut self, hyperlink: Option<Hyperlink>) {
+        self.hyperlink = hyperlink.map(|hyperlink| {
+            // Only links with an id are known to be the same link when opened again
+            let existing = self
+                .hyperlinks
+                .iter()
+                .position(|link| hyperlink.id.is_some() && *link == hyperlink);
+
+            existing.unwrap_or_else(|| {
+                self.hyperlinks.push(hyperlink);
+                self.hyperlinks.len() - 1
+            })
+        });
+    }
+
+    pub fn designate_charset(&mut self, index: CharsetIndex, charset: StandardCharset) {
+        self.cursor.charsets[index as usize] = charset;
+    }
+
+    pub fn set_active_charset(&mut self, index: CharsetIndex) {
+        self.cursor.active_charset = index;
+    }
+
+    pub fn single_shift(&mut self, index: CharsetIndex) {
+        self.single_shift = Some(index);
+    }
+
+    /// Translate a received character through the character set in use.
+    pub fn map_char(&mut self, c: char) -> char {
+        let index = self
+            .single_shift
+            .take()
+            .unwrap_or(self.cursor.active_charset);
+        self.cursor.charsets[index as usize].map(c)
+    }
+
+    pub fn input(&mut self, c: char) {
+        self.write(Cell {
+            c,
+            style: self.cursor.style,
+            hyperlink: self.hyperlink,
+        });
+    }
+
+    /// Print `c` over the character under the cursor, combining the two like `nroff` output
+    /// expects: `X\bX` is bold and `_\bX` (or `X\b_`) is underlined.
+    pub fn overstrike(&mut self, c: char) {
+        let cell = self.grid[self.cursor.line][self.cursor.column];
+        let mut style = cell.style;
+
+        let c = match (cell.c, c) {
+            (' ', _) | (_, ' ') => return self.input(c),
+            (previous, c) if previous == c => {
+                style.bold = true;
+                c
+            }
+            ('_', c) | (c, '_') => {
+                style.underline = Underline::Single;
+                c
+            }
+            _ => return self.input(c),
+        };
+
+        self.write(Cell {
+            c,
+            style,
+            hyperlink: self.hyperlink,
+        });
+    }
+
+    pub fn backspace(&mut self) {
+        self.move_backward(1);
+    }
+
+    pub fn linefeed(&mut self) {
+        let next = self.cursor.line + 1;
+        if next == self.scroll_region.end {
+            self.scroll_up(1);
+        } else if next < self.lines {
+            self.cursor.line = next;
+        }
+    }
+
+    /// Move up a line, scrolling the region down when at its top margin.
+    pub fn reverse_index(&mut self) {
+        if self.cursor.line == self.scroll_region.start {
+            self.scroll_down(1);
+        } else if self.cursor.line > 0 {
+            self.cursor.line -= 1;
+        }
+    }
+
+    /// Set the top and bottom margins (1-based, inclusive) and home the cursor.
+    pub fn set_scroll_region(&mut self, top: usize, bottom: usize) {
+        let bottom = bottom.min(self.lines);
+        if top >= bottom {
+            return;
+        }
+
+        self.scroll_region = top - 1..bottom;
+        self.goto(0, 0);
+    }
+
+    /// Scroll the region up, discarding lines at the top. They're kept in the history when the
+    /// region starts at the top of the primary screen, like xterm does.
+    pub fn scroll_up(&mut self, count: usize) {
+        let region = self.scroll_region.clone();
+        let save = region.start == 0 && !self.alt_active;
+        self.scroll_up_in(region, count, save);
+    }
+
+    /// Scroll the region down, inserting blank lines at the top.
+    pub fn scroll_down(&mut self, count: usize) {
+        self.scroll_down_in(self.scroll_region.clone(), count);
+    }
+
+    /// Insert blank lines at the cursor, pushing lines below it down within the scroll region.
+    pub fn insert_lines(&mut self, count: usize) {
+        let line = self.cursor.line;
+        if self.scroll_region.contains(&line) {
+            self.scroll_down_in(line..self.scroll_region.end, count);
+            self.carriage_return();
+        }
+    }
+
+    /// Delete lines at the cursor, pulling lines below it up within the scroll region.
+    pub fn delete_lines(&mut self, count: usize) {
+        let line = self.cursor.line;
+        if self.scroll_region.contains(&line) {
+            self.scroll_up_in(line..self.scroll_region.end, count, false);
+            self.carriage_return();
+        }
+    }
+
+    /// Insert blank characters at the cursor, shifting the rest of the line right.
+    pub fn insert_chars(&mut self, count: usize) {
+        let blank = self.blank_cell();
+        let column = self.cursor.column;
+        let count = count.min(self.columns - column);
+        let row = &mut self.grid[self.cursor.line];
+
+        row.truncate(self.columns - count);
+        row.splice(column..column, iter::repeat_n(blank, count));
+        self.cursor.input_needs_wrap = false;
+    }
+
+    /// Delete characters at the cursor, shifting the rest of the line left.
+    pub fn delete_chars(&mut self, count: usize) {
+        let blank = self.blank_cell();
+        let column = self.cursor.column;
+        let count = count.min(self.columns - column);
+        let row = &mut self.grid[self.cursor.line];
+
+        row.drain(column..column + count);
+        row.resize(self.columns, blank);
+        self.cursor.input_needs_wrap = false;
+    }
+
+    pub fn carriage_return(&mut self) {
+        self.cursor.column = 0;
+        self.cursor.input_needs_wrap = false;
+    }
+
+    /// Move the cursor to an absolute position, clamped to the screen.
+    pub fn goto(&mut self, line: usize, column: usize) {
+        self.cursor.line = line.min(self.lines - 1);
+        self.cursor.column = column.min(self.columns - 1);
+        self.cursor.input_needs_wrap = false;
+    }
+
+    pub fn goto_line(&mut self, line: usize) {
+        self.goto(line, self.cursor.column);
+    }
+
+    pub fn goto_column(&mut self, column: usize) {
+        self.goto(self.cursor.line, column);
+    }
+
+    /// Move up, stopping at the top margin if the cursor is inside the scroll region.
+    pub fn move_up(&mut self, count: usize) {
+        let top = if self.cursor.line >= self.scroll_region.start {
+            self.scroll_region.start
+        } else {
+            0
+        };
+        self.goto_line(self.cursor.line.saturating_sub(count).max(top));
+    }
+
+    /// Move down, stopping at the bottom margin if the cursor is inside the scroll region.
+    pub fn move_down(&mut self, count: usize) {
+        let bottom = if self.cursor.line < self.scroll_region.end {
+            self.scroll_region.end - 1
+        } else {
+            self.lines - 1
+        };
+        self.goto_line(self.cursor.line.saturating_add(count).min(bottom));
+    }
+
+    pub fn move_forward(&mut self, count: usize) {
+        self.goto_column(self.cursor.column.saturating_add(count));
+    }
+
+    pub fn move_backward(&mut self, count: usize) {
+        self.goto_column(self.cursor.column.saturating_sub(count));
+    }
+
+    /// Erase from the cursor to the end of the screen (0), from the start of the screen to the
+    /// cursor (1), the whole screen (2) or the scrollback history (3).
+    pub fn erase_in_display(&mut self, mode: usize) {
+        let line = self.cursor.line;
+        match mode {
+            0 => {
+                self.erase_in_line(0);
+                self.erase_lines(line + 1..self.lines);
+            }
+            1 => {
+                self.erase_lines(0..line);
+                self.erase_in_line(1);
+            }
+            2 => self.erase_lines(0..self.lines),
+            3 => self.history.clear(),
+            _ => {}
+        }
+    }
+
+    /// Erase from the cursor to the end of the line (0), from the start of the line to the cursor
+    /// (1) or the whole line (2).
+    pub fn erase_in_line(&mut self, mode: usize) {
+        let column = self.cursor.column;
+        match mode {
+            0 => self.erase_cells(column..self.columns),
+            1 => self.erase_cells(0..column + 1),
+            2 => self.erase_cells(0..self.columns),
+            _ => {}
+        }
+    }
+
+    /// Erase `count` characters starting at the cursor without moving anything.
+    pub fn erase_chars(&mut self, count: usize) {
+        let column = self.cursor.column;
+        let end = column.saturating_add(count).min(self.columns);
+        self.erase_cells(column..end);
+    }
+
+    fn write(&mut self, cell: Cell) {
+        if self.cursor.input_needs_wrap {
+            self.carriage_return();
+            self.linefeed();
+        }
+
+        self.last_char = Some(cell.c);
+
+        let cursor = &mut self.cursor;
+        self.grid[cursor.line][cursor.column] = cell;
+
+        if cursor.column + 1 < self.columns {
+            cursor.column += 1;
+        } else {
+            cursor.input_needs_wrap = true;
+        }
+    }
+
+    /// Erased cells keep the current background color but nothing else.
+    fn blank_cell(&self) -> Cell {
+        Cell {
+            c: ' ',
+            style: Style {
+                bg: self.cursor.style.bg,
+                ..Style::default()
+            },
+            hyperlink: None,
+        }
+    }
+
+    fn erase_cells(&mut self, columns: Range<usize>) {
+        let blank = self.blank_cell();
+        for cell in &mut self.grid[self.cursor.line][columns] {
+            *cell = blank;
+        }
+    }
+
+    fn erase_lines(&mut self, lines: Range<usize>) {
+        let blank = self.blank_cell();
+        for row in &mut self.grid[lines] {
+            for cell in row.iter_mut() {
+                *cell = blank;
+            }
+        }
+    }
+
+    /// Move forward to the `count`th next tab stop, or the last column if there isn't one.
+    pub fn tab_forward(&mut self, count: usize) {
+        let mut column = self.cursor.column;
+        for _ in 0..count {
+            column = (column + 1..self.columns)
+                .find(|&column| self.tabs[column])
+                .unwrap_or(self.columns - 1);
+        }
+        self.goto_column(column);
+    }
+
+    /// Move back to the `count`th previous tab stop, or the first column if there isn't one.
+    pub fn tab_backward(&mut self, count: usize) {
+        let mut column = self.cursor.column;
+        for _ in 0..count {
+            column = (0..column)
+                .rev()
+                .find(|&column| self.tabs[column])
+                .unwrap_or(0);
+        }
+        self.goto_column(column);
+    }
+
+    /// Set a tab stop at the cursor.
+    pub fn set_tab_stop(&mut self) {
+        self.tabs[self.cursor.column] = true;
+    }
+
+    /// Clear the tab stop at the cursor (0) or all tab stops (3).
+    pub fn clear_tab_stops(&mut self, mode: usize) {
+        match mode {
+            0 => self.tabs[self.cursor.column] = false,
+            3 => self.tabs.iter_mut().for_each(|tab| *tab = false),
+            _ => {}
+        }
+    }
+
+    /// Print the last printed character `count` more times.
+    pub fn repeat(&mut self, count: usize) {
+        if let Some(c) = self.last_char {
+            // Anything more than a screenful just overwrites itself
+            for _ in 0..count.min(self.columns * self.lines) {
+                self.input(c);
+            }
+        }
+    }
+
+    pub fn save_cursor(&mut self) {
+        self.saved_cursor = self.cursor;
+    }
+
+    pub fn restore_cursor(&mut self) {
+        self.cursor = self.saved_cursor;
+    }
+
+    /// Switch to the alternate screen, optionally clearing it first.
+    pub fn enter_alt_screen(&mut self, clear: bool) {
+        if self.alt_active {
+            return;
+        }
+
+        self.swap_grids();
+        if clear {
+            self.erase_in_display(2);
+        }
+    }
+
+    /// Switch back to the primary screen, optionally clearing the alternate screen first.
+    pub fn leave_alt_screen(&mut self, clear: bool) {
+        if !self.alt_active {
+            return;
+        }
+
+        if clear {
+            self.erase_in_display(2);
+        }
+        self.swap_grids();
+    }
+
+    fn swap_grids(&mut self) {
+        mem::swap(&mut self.grid, &mut self.inactive_grid);
+        mem::swap(&mut self.saved_cursor, &mut self.inactive_saved_cursor);
+        self.alt_active = !self.alt_active;
+    }
+
+    pub fn terminal_attribute(&mut self, attr: Attr) {
+        self.cursor.style.apply(attr);
+    }
+
+    fn scroll_up_in(&mut self, region: Range<usize>, count: usize, save: bool) {
+        let count = count.min(region.len());
+        let blank = vec![self.blank_cell(); self.columns];
+
+        let removed = self.grid.drain(region.start..region.start + count);
+        if save {
+            self.history.extend(removed);
+        } else {
+            drop(removed);
+        }
+
+        let end = region.end - count;
+        self.grid.splice(end..end, iter::repeat_n(blank, count));
+    }
+
+    fn scroll_down_in(&mut self, region: Range<usize>, count: usize) {
+        let count = count.min(region.len());
+        let blank = vec![self.blank_cell(); self.columns];
+
+        self.grid.drain(region.end - count..region.end);
+        self.grid
+            .splice(region.start..region.start, iter::repeat_n(blank, count));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 5x5 screen with each line filled with its number.
+    fn numbered_screen() -> Screen {
+        let mut screen = Screen::new(5, 5);
+        for line in 0..5 {
+            screen.goto(line, 0);
+            screen.input(char::from(b'0' + line as u8));
+        }
+        screen
+    }
+
+    /// The first character of each row, with blank rows as spaces.
+    fn first_chars(screen: &Screen, buffer: Buffer) -> String {
+        screen.rows(buffer).map(|row| row[0].c).collect()
+    }
+
+    #[test]
+    fn linefeed_at_bottom_margin_scrolls_region() {
+        let mut screen = numbered_screen();
+        screen.set_scroll_region(2, 4);
+        screen.goto(3, 0);
+
+        screen.linefeed();
+        screen.linefeed();
+
+        assert_eq!(first_chars(&screen, Buffer::Primary), "03  4");
+        assert_eq!(screen.cursor.line, 3);
+    }
+
+    #[test]
+    fn linefeed_below_region_stops_at_last_line() {
+        let mut screen = numbered_screen();
+        screen.set_scroll_region(1, 3);
+        screen.goto(4, 0);
+
+        screen.linefeed();
+
+        assert_eq!(first_chars(&screen, Buffer::Primary), "01234");
+        assert_eq!(screen.cursor.line, 4);
+    }
+
+    #[test]
+    fn reverse_index_at_top_margin_scrolls_region_down() {
+        let mut screen = numbered_screen();
+        screen.set_scroll_region(2, 4);
+        screen.goto(1, 0);
+
+        screen.reverse_index();
+
+        assert_eq!(first_chars(&screen, Buffer::Primary), "0 124");
+        assert_eq!(screen.cursor.line, 1);
+    }
+
+    #[test]
+    fn reverse_index_at_top_of_screen_outside_region() {
+        let mut screen = numbered_screen();
+        screen.set_scroll_region(2, 4);
+        screen.goto(0, 0);
+
+        screen.reverse_index();
+
+        assert_eq!(first_chars(&screen, Buffer::Primary), "01234");
+        assert_eq!(screen.cursor.line, 0);
+    }
+
+    #[test]
+    fn insert_lines_larger_than_region() {
+        let mut screen = numbered_screen();
+        screen.set_scroll_region(2, 4);
+        screen.goto(2, 0);
+
+        screen.insert_lines(10);
+
+        assert_eq!(first_chars(&screen, Buffer::Primary), "01  4");
+    }
+
+    #[test]
+    fn delete_lines_larger_than_region() {
+        let mut screen = numbered_screen();
+        screen.set_scroll_region(2, 4);
+        screen.goto(1, 0);
+
+        screen.delete_lines(10);
+
+        assert_eq!(first_chars(&screen, Buffer::Primary), "0   4");
+    }
+
+    #[test]
+    fn insert_and_delete_lines_outside_region_do_nothing() {
+        let mut screen = numbered_screen();
+        screen.set_scroll_region(2, 4);
+        screen.goto(4, 0);
+
+        screen.insert_lines(1);
+        screen.delete_lines(1);
+
+        assert_eq!(first_chars(&screen, Buffer::Primary), "01234");
+    }
+
+    #[test]
+    fn scroll_up_from_top_saves_history_on_primary_screen() {
+        let mut screen = numbered_screen();
+
+        screen.scroll_up(2);
+
+        assert_eq!(screen.history.len(), 2);
+        assert_eq!(first_chars(&screen, Buffer::Primary), "01234  ");
+    }
+
+    #[test]
+    fn scroll_up_below_top_discards_lines() {
+        let mut screen = numbered_screen();
+        screen.set_scroll_region(2, 5);
+
+        screen.scroll_up(2);
+
+        assert!(screen.history.is_empty());
+        assert_eq!(first_chars(&screen, Buffer::Primary), "034  ");
+    }
+
+    #[test]
+    fn scroll_up_on_alternate_screen_discards_lines() {
+        let mut screen = Screen::new(5, 5);
+        screen.enter_alt_screen(true);
+        screen.input('a');
+
+        screen.scroll_up(1);
+
+        assert!(screen.history.is_empty());
+        assert_eq!(first_chars(&screen, Buffer::Alternate), "     ");
+    }
+
+    #[test]
+    fn scroll_up_count_larger_than_region() {
+        let mut screen = numbered_screen();
+
+        screen.scroll_up(10);
+
+        assert_eq!(screen.history.len(), 5);
+        assert_eq!(first_chars(&screen, Buffer::Primary), "01234     ");
+    }
+}